@@ -0,0 +1,16 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use fxyt::Program;
+
+fn render(c: &mut Criterion) {
+    let xor = Program::compile("XY^").unwrap();
+    c.bench_function("render XY^", |b| b.iter(|| black_box(&xor).render()));
+
+    // keeps the stack busy, so allocation per pixel would show up
+    let deep = Program::compile("XYN3N4N5N6PPPP*N255%DD").unwrap();
+    c.bench_function("render deep stack", |b| {
+        b.iter(|| black_box(&deep).render())
+    });
+}
+
+criterion_group!(benches, render);
+criterion_main!(benches);
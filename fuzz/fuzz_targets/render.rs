@@ -0,0 +1,37 @@
+#![no_main]
+
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use fxyt::{Program, RenderOptions};
+use libfuzzer_sys::fuzz_target;
+
+// Any input, FXYT or not, should come back as an error at worst, never a panic.
+fuzz_target!(|data: &[u8]| {
+    // the first few bytes pick the options, and the rest is the program
+    let Some((settings, source)) = data.split_first_chunk::<11>() else {
+        return;
+    };
+    let [width, height, frames, t_start @ ..] = *settings;
+
+    let _ = fxyt::parse_all(&String::from_utf8_lossy(source));
+    let Ok(program) = Program::compile_bytes(source) else {
+        return;
+    };
+    let _ = program.info();
+    Program::compile(&program.format()).expect("formatted programs should parse");
+
+    // kept small, with a budget, so huge canvases and loop counts don't make every run time
+    // out, but T can start anywhere
+    let options = RenderOptions::new()
+        .size(usize::from(width % 8), usize::from(height % 8))
+        .frames(usize::from(frames % 4))
+        .t_start(u64::from_le_bytes(t_start) as usize)
+        .seamless(frames & 0x80 != 0)
+        .max_ops(10_000)
+        .debug_output(Arc::new(Mutex::new(io::sink())));
+    let _ = program.render_with(&options);
+    let mut optimized = program;
+    optimized.optimize(&options);
+    let _ = optimized.render_with(&options);
+});
@@ -1,466 +1,6334 @@
-use std::fmt::Display;
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
 
-use rgb::RGB8;
+extern crate alloc;
+
+use alloc::{collections::BTreeMap, format, string::String, vec, vec::Vec};
+use core::borrow::Borrow;
+use core::fmt::Display;
+use core::ops::Range;
+use core::str::FromStr;
+#[cfg(feature = "std")]
+use std::io::{self, Write};
+#[cfg(feature = "std")]
+use std::sync::{Arc, Mutex, PoisonError};
+
+use rgb::{RGB8, RGBA8};
 use thiserror::Error;
 
 pub fn render(program: &str) -> Result<Vec<Frame>, FxytError> {
-    let parsed = parse(program, 0, 0)?.1;
+    Program::compile(program)?.render()
+}
 
-    let t_range = if program.contains(|c| c == 'T' || c == 't') {
-        0..256
-    } else {
-        0..1
+/// Parses a program and writes it back out laid out consistently:
+///
+/// - Commands go one after another on the same line, without spaces, apart from a space
+///   between `<` or `>` and a following `=`, `<=` or `>=`.
+/// - The opening bracket of a loop (`[`, `?[` or `L[`) ends its line. The body goes on the
+///   lines after, indented by four more spaces, then the `]` goes on a line of its own at the
+///   loop's indentation, and whatever comes after starts a new line.
+/// - Loops with nothing in them stay on the line, as `[]`.
+/// - Comments and blank lines are dropped, and every line ends with a newline.
+///
+/// Formatting already formatted source gives back the same thing.
+pub fn format(program: &str) -> Result<String, ParseError> {
+    Ok(Program::compile(program)?.format())
+}
+
+/// Checks that a program parses and describes it, without rendering anything. Runtime errors
+/// like an empty stack aren't caught, since finding those means running it.
+pub fn validate(program: &str) -> Result<ProgramInfo, FxytError> {
+    Ok(Program::compile(program)?.info())
+}
+
+/// Parses a program, reporting every mistake in it instead of just the first, along with a
+/// best guess at the program. See [`Program::compile_all`].
+pub fn parse_all(program: &str) -> (Program, Vec<ParseError>) {
+    Program::compile_all(program)
+}
+
+/// Like [`render`], but with non-default [`RenderOptions`].
+pub fn render_with(program: &str, options: &RenderOptions) -> Result<Vec<Frame>, FxytError> {
+    Program::compile(program)?.render_with(options)
+}
+
+/// Like [`render`], but calls `progress` after each frame is finished with the number of
+/// frames done so far and the total, e.g. to drive a progress bar.
+pub fn render_with_progress(
+    program: &str,
+    progress: &mut dyn FnMut(usize, usize),
+) -> Result<Vec<Frame>, FxytError> {
+    Program::compile(program)?.render_with_progress(progress)
+}
+
+/// Like [`render`], but onto a `width` by `height` canvas instead of 256x256. `X` and `Y` run
+/// over the canvas, so a larger canvas shows more of the pattern rather than a scaled up one.
+pub fn render_sized(program: &str, width: usize, height: usize) -> Result<Vec<Frame>, FxytError> {
+    Program::compile(program)?.render_sized(width, height)
+}
+
+/// Renders just the `width` by `height` rectangle whose bottom left corner is at `x0, y0`, at
+/// time `t`. Coordinates are the same as in a full frame, so this is a window onto the same
+/// pattern, with rows from top to bottom like any other [`Frame`].
+pub fn render_region(
+    program: &str,
+    x0: usize,
+    y0: usize,
+    width: usize,
+    height: usize,
+    t: usize,
+) -> Result<Frame, FxytError> {
+    Program::compile(program)?.render_region(x0, y0, width, height, t)
+}
+
+/// Renders a `width` by `height` frame at time `t` one `tile` by `tile` block at a time,
+/// handing each to `callback` along with its bottom left corner as soon as it's done, so the
+/// whole frame never has to be held in memory. Each tile is what [`render_region`] would give
+/// for it, and tiles on the top and right edges are cut short if `tile` doesn't divide the
+/// frame evenly. Tiles go row by row from the bottom left. A `tile` of 0 is treated as 1.
+pub fn render_tiles(
+    program: &str,
+    width: usize,
+    height: usize,
+    tile: usize,
+    t: usize,
+    callback: &mut dyn FnMut(usize, usize, Frame),
+) -> Result<(), FxytError> {
+    Program::compile(program)?.render_tiles(width, height, tile, t, callback)
+}
+
+/// Like [`render_with`], but also counts how many times each command runs, and how deep the
+/// stack gets. Every pixel is rendered on the current thread, so this is slower than a plain
+/// render, but rendering without it isn't slowed down.
+pub fn render_with_stats(
+    program: &str,
+    options: &RenderOptions,
+) -> Result<(Vec<Frame>, Stats), FxytError> {
+    Program::compile(program)?.render_with_stats(options)
+}
+
+/// Like [`render`], but takes the program as raw bytes. FXYT is ASCII-only, so no UTF-8
+/// validation is needed; any non-ASCII byte outside of a comment is reported as an invalid
+/// character.
+pub fn render_bytes(program: &[u8]) -> Result<Vec<Frame>, FxytError> {
+    Program::compile_bytes(program)?.render()
+}
+
+/// Renders only the frame at time `t`, for previewing or scrubbing through an animation
+/// without rendering every frame. `t` is ignored by programs that don't use `T`.
+pub fn render_frame(program: &str, t: usize) -> Result<Frame, FxytError> {
+    Program::compile(program)?.render_frame(t)
+}
+
+/// Yields frames one at a time as they're rendered, rather than holding them all in memory
+/// like [`render`] does. Parse errors are returned straight away, while errors from running
+/// the program come out of the iterator.
+pub fn render_frames(
+    program: &str,
+) -> Result<impl Iterator<Item = Result<Frame, FxytError>>, FxytError> {
+    let program = Program::compile(program)?;
+    Ok(frame_iter(program, &RenderOptions::default()))
+}
+
+/// Evaluates the program at a single point, without rendering anything else.
+pub fn render_pixel(program: &str, x: usize, y: usize, t: usize) -> Result<RGB8, FxytError> {
+    Program::compile(program)?.render_pixel(x, y, t)
+}
+
+/// Renders the single row at height `y` and time `t`, indexed by `x`. Handy for building
+/// 1D gradient ramps or lookup tables out of FXYT expressions.
+pub fn render_row(program: &str, y: usize, t: usize) -> Result<[RGB8; 256], FxytError> {
+    Program::compile(program)?.render_row(y, t)
+}
+
+/// Renders the single column at `x` and time `t`, indexed by `y` (so bottom to top, unlike
+/// the rows of a [`Frame`]).
+pub fn render_column(program: &str, x: usize, t: usize) -> Result<[RGB8; 256], FxytError> {
+    Program::compile(program)?.render_column(x, t)
+}
+
+/// Renders a program (as with [`render_with`]) and encodes every frame into an animated PNG,
+/// keeping full 24-bit colour and each frame's interval.
+#[cfg(feature = "apng")]
+pub fn render_apng(program: &str, options: &RenderOptions) -> Result<Vec<u8>, FxytError> {
+    let frames = render_with(program, options)?;
+    let mut out = Vec::new();
+    write_apng(&frames, &mut out).map_err(|error| FxytError::Encoding(error.to_string()))?;
+    Ok(out)
+}
+
+/// Encodes frames as an animated PNG, using each frame's interval as its delay. A single frame
+/// is written as a plain still PNG instead.
+#[cfg(feature = "apng")]
+pub fn write_apng<W: Write>(frames: &[Frame], out: W) -> io::Result<()> {
+    if let [frame] = frames {
+        return write_png(frame, out);
+    }
+
+    let (width, height) = frames.first().map_or((0, 0), |f| (f.width(), f.height()));
+    let mut writer = ApngWriter::new(out, width, height, frames.len())?;
+    for frame in frames {
+        writer.write_frame(frame)?;
+    }
+    writer.finish()
+}
+
+/// Encodes frames as a looping GIF, using each frame's interval as its delay. GIFs can only
+/// show 256 colours at once, so each frame is quantized down to its own palette.
+#[cfg(feature = "gif")]
+pub fn write_gif<W: Write>(frames: &[Frame], out: W) -> io::Result<()> {
+    write_gif_with(frames, out, &GifOptions::default())
+}
+
+/// Like [`write_gif`], but with non-default [`GifOptions`].
+#[cfg(feature = "gif")]
+pub fn write_gif_with<W: Write>(frames: &[Frame], out: W, options: &GifOptions) -> io::Result<()> {
+    /// Quantizes every frame together, so they can all share the one palette. Returns the
+    /// palette and a lookup from colours to their index in it.
+    fn global_palette(frames: &[Frame]) -> (Vec<u8>, IndexOf) {
+        let pixels = || frames.iter().flat_map(|frame| frame.image.iter().flatten());
+        let colours: std::collections::BTreeSet<_> =
+            pixels().map(|pixel| (pixel.r, pixel.g, pixel.b)).collect();
+        if colours.len() <= 256 {
+            // NeuQuant isn't exact even with few colours, so keep them as they are
+            let palette = colours.iter().flat_map(|&(r, g, b)| [r, g, b]).collect();
+            let colours: Vec<_> = colours.into_iter().collect();
+            let index_of = move |pixel: &RGB8| {
+                colours
+                    .binary_search(&(pixel.r, pixel.g, pixel.b))
+                    .unwrap_or(0) as u8
+            };
+            return (palette, Box::new(index_of));
+        }
+
+        let rgba: Vec<u8> = pixels()
+            .flat_map(|pixel| [pixel.r, pixel.g, pixel.b, 255])
+            .collect();
+        let quantizer = color_quant::NeuQuant::new(10, 256, &rgba);
+        let palette = quantizer.color_map_rgb();
+        let index_of =
+            move |pixel: &RGB8| quantizer.index_of(&[pixel.r, pixel.g, pixel.b, 255]) as u8;
+        (palette, Box::new(index_of))
+    }
+
+    let (width, height) = frames.first().map_or((0, 0), |f| (f.width(), f.height()));
+    let dithered: Vec<Frame>;
+    let frames = match options.dither {
+        true => {
+            dithered = frames.iter().map(dither_frame).collect();
+            &dithered
+        }
+        false => frames,
     };
+    let global = options.global_palette.then(|| global_palette(frames));
+    // the palette has to come from the dithered frames, so they're dithered here instead
+    let options = GifOptions {
+        dither: false,
+        ..*options
+    };
+    let mut writer = GifWriter::with_palette(out, width, height, &options, global)?;
+    for frame in frames {
+        writer.write_frame(frame)?;
+    }
+    writer.finish()?;
+
+    Ok(())
+}
+
+/// Like [`render`], but with each frame packed into RGBA bytes (see [`Frame::to_rgba_bytes`]).
+pub fn render_rgba(program: &str) -> Result<Vec<FrameBytes>, FxytError> {
+    Ok(render(program)?
+        .iter()
+        .map(Frame::<RGB8>::to_rgba_bytes)
+        .collect())
+}
+
+/// Like [`render`], but with an alpha channel. If the program leaves more than three values
+/// on the stack, the top one is alpha and the three under it are red, green and blue.
+/// Otherwise alpha is 255 and the colour is read as usual.
+pub fn render_rgba8(program: &str) -> Result<Vec<Frame<RGBA8>>, FxytError> {
+    Program::compile(program)?.render_rgba8()
+}
 
-    let mut frames = Vec::with_capacity(t_range.len());
-    for t in t_range {
-        let mut canvas = [[RGB8::default(); 256]; 256];
+/// Like [`render`], but with each frame packed into RGB bytes (see [`Frame::to_rgb_bytes`]).
+pub fn render_rgb(program: &str) -> Result<Vec<FrameBytes>, FxytError> {
+    Ok(render(program)?.iter().map(Frame::to_rgb_bytes).collect())
+}
 
-        let mut frame_interval = 100;
+/// Draws a frame for a truecolour terminal, `cols` characters wide. Each character is a `▀`
+/// half block covering two pixels vertically (foreground on top, background below), and each
+/// of those is the average of the block of frame pixels it covers.
+pub fn preview_ansi(frame: &Frame, cols: usize) -> String {
+    let (width, height) = (frame.width(), frame.height());
+    if cols == 0 || width == 0 || height == 0 {
+        return String::new();
+    }
 
-        for x in 0..256 {
-            #[allow(clippy::needless_range_loop)] //this is cleaner than what clippy wants
-            for y in 0..256 {
-                canvas[255 - y][x] =
-                    render_to_pixel(&parsed, &mut frame_interval, Coords::new(x, y, t))?;
+    // keep the aspect ratio, with each half block being roughly square
+    let half_rows = (height * cols / width).max(1);
+    let span = |index: usize, count: usize, len: usize| {
+        let start = index * len / count;
+        start..((index + 1) * len / count).max(start + 1).min(len)
+    };
+    let average = |column: usize, half_row: usize| {
+        let (mut sum, mut count) = ([0usize; 3], 0);
+        for row in &frame.image[span(half_row, half_rows, height)] {
+            for pixel in &row[span(column, cols, width)] {
+                sum[0] += pixel.r as usize;
+                sum[1] += pixel.g as usize;
+                sum[2] += pixel.b as usize;
+                count += 1;
             }
         }
+        sum.map(|channel| channel / count)
+    };
 
-        frames.push(Frame {
-            interval: frame_interval,
-            image: canvas,
-        });
+    let mut preview = String::new();
+    for half_row in (0..half_rows).step_by(2) {
+        for column in 0..cols {
+            let [r, g, b] = average(column, half_row);
+            preview += &format!("\x1b[38;2;{r};{g};{b}m");
+            if half_row + 1 < half_rows {
+                let [r, g, b] = average(column, half_row + 1);
+                preview += &format!("\x1b[48;2;{r};{g};{b}m");
+            } else {
+                preview += "\x1b[49m";
+            }
+            preview += "▀";
+        }
+        preview += "\x1b[0m\n";
     }
 
-    Ok(frames)
+    preview
 }
 
-fn render_to_pixel(
-    commands: &[Command],
-    frame_interval: &mut isize,
-    coords: Coords,
-) -> Result<RGB8, FxytError> {
-    let mut stack = Vec::with_capacity(8);
-    let mut mode = 0;
+/// Encodes a frame as an 8-bit RGB PNG.
+#[cfg(feature = "png")]
+pub fn write_png<W: Write>(frame: &Frame, out: W) -> io::Result<()> {
+    let mut encoder = png::Encoder::new(out, frame.width() as u32, frame.height() as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
 
-    if let Some(colour) = render_to_stack(commands, &mut stack, &mut mode, frame_interval, coords)?
-    {
-        return Ok(colour);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&frame.rgb_bytes())?;
+    writer.finish()?;
+
+    Ok(())
+}
+
+/// Writes a frame as a binary (P6) PPM. No compression, but no dependencies either.
+#[cfg(feature = "std")]
+pub fn write_ppm<W: Write>(frame: &Frame, mut out: W) -> io::Result<()> {
+    writeln!(out, "P6\n{} {}\n255", frame.width(), frame.height())?;
+
+    for row in &frame.image {
+        for pixel in row {
+            out.write_all(&[pixel.r, pixel.g, pixel.b])?;
+        }
     }
 
-    let blue = stack.pop().unwrap_or_default();
-    let green = stack.pop().unwrap_or_default();
-    let red = stack.pop().unwrap_or_default();
+    Ok(())
+}
+
+/// Spells out an error and everything that caused it as one message, so that showing it
+/// somewhere that only takes a string doesn't lose the parse position behind a runtime error.
+pub fn describe_error(error: &dyn core::error::Error) -> String {
+    let mut message = format!("{error}");
+    let mut source = error.source();
+    while let Some(cause) = source {
+        message += &format!(": {cause}");
+        source = cause.source();
+    }
+    message
+}
+
+/// Bindings for rendering from JavaScript, e.g. into an `ImageData` for a browser playground.
+#[cfg(feature = "wasm")]
+pub mod wasm {
+    use wasm_bindgen::prelude::*;
+
+    use crate::Program;
 
-    if red > 255 || green > 255 || blue > 255 || red < 0 || green < 0 || blue < 0 {
-        return Err(FxytError::RgbOutOfRange);
+    /// Renders the frame at time `t` as 256x256 RGBA bytes.
+    #[wasm_bindgen]
+    pub fn render_frame_rgba(program: &str, t: u32) -> Result<Vec<u8>, JsValue> {
+        let frame = Program::compile(program)
+            .map_err(to_js)?
+            .render_frame(t as usize)
+            .map_err(to_js)?;
+        Ok(frame.to_rgba_bytes().data)
     }
 
-    Ok(RGB8::new(red as u8, green as u8, blue as u8))
+    /// How many frames the program renders to: 256 if it's animated, otherwise 1.
+    #[wasm_bindgen]
+    pub fn frame_count(program: &str) -> Result<u32, JsValue> {
+        let program = Program::compile(program).map_err(to_js)?;
+        Ok(if program.is_animated() { 256 } else { 1 })
+    }
+
+    /// See [`describe_error`](crate::describe_error).
+    fn to_js(error: impl core::error::Error) -> JsValue {
+        JsValue::from_str(&crate::describe_error(&error))
+    }
 }
 
-fn render_to_stack(
-    commands: &[Command],
-    stack: &mut Vec<isize>,
-    mode: &mut u8,
-    frame_interval: &mut isize,
-    coords: Coords,
-) -> Result<Option<RGB8>, FxytError> {
-    for command in commands {
-        match command {
-            Command::Coordinates(c) => match c {
-                Coordinates::X => stack.push(coords.x),
-                Coordinates::Y => stack.push(coords.y),
-                Coordinates::T => stack.push(coords.t),
-            },
-            Command::Integer => stack.push(0),
-            Command::Digit(d) => {
-                let top = stack.pop().ok_or(FxytError::StackEmpty)?;
-                stack.push(top * 10 + *d as isize)
-            }
-            Command::Arithmetic(a) => {
-                let right = stack.pop().ok_or(FxytError::StackEmpty)?;
-                let left = stack.pop().ok_or(FxytError::StackEmpty)?;
-                stack.push(match a {
-                    Arithmetic::Plus => left + right,
-                    Arithmetic::Minus => left - right,
-                    Arithmetic::Times => left * right,
-                    Arithmetic::Divide => {
-                        if right != 0 {
-                            left / right
-                        } else {
-                            match mode {
-                                0 => return Err(FxytError::DivideByZero),
-                                1 => return Ok(Some(RGB8::default())),
-                                2 => return Ok(Some(RGB8::new(255, 0, 0))),
-                                _ => unreachable!(),
-                            }
-                        }
-                    }
-                    Arithmetic::Modulus => left % right,
-                })
-            }
-            Command::Mode => *mode += 1,
-            Command::Comparison(c) => {
-                let right = stack.pop().ok_or(FxytError::StackEmpty)?;
-                let left = stack.pop().ok_or(FxytError::StackEmpty)?;
-                stack.push(match c {
-                    Comparison::Equals => left == right,
-                    Comparison::LessThan => left < right,
-                    Comparison::GreaterThan => left > right,
-                } as isize)
-            }
-            Command::Invert => {
-                let arg = stack.pop().ok_or(FxytError::StackEmpty)?;
-                stack.push((arg == 0) as isize)
-            }
-            Command::Bitwise(b) => {
-                let right = stack.pop().ok_or(FxytError::StackEmpty)?;
-                let left = stack.pop().ok_or(FxytError::StackEmpty)?;
-                stack.push(match b {
-                    Bitwise::Xor => left ^ right,
-                    Bitwise::And => left & right,
-                    Bitwise::Or => left | right,
-                })
-            }
-            Command::Clip => {
-                let arg = stack.pop().ok_or(FxytError::StackEmpty)?;
-                stack.push(arg.clamp(0, 255))
-            }
-            Command::StackOperation(so) => match so {
-                StackOperation::Duplicate => {
-                    let arg = stack.pop().ok_or(FxytError::StackEmpty)?;
-                    stack.push(arg);
-                    stack.push(arg);
-                }
-                StackOperation::Pop => {
-                    stack.pop().ok_or(FxytError::StackEmpty)?;
+impl Program {
+    /// Parses a program once so it can be rendered as many times as needed.
+    pub fn compile(src: &str) -> Result<Program, ParseError> {
+        Self::compile_bytes(src.as_bytes())
+    }
+
+    /// Like [`Program::compile`], but carries on past mistakes rather than stopping at the
+    /// first, to find all of them at once. What's wrong is skipped as if it were whitespace: a
+    /// bad character, an unclosed bracket or a hex literal that's too large, or everything if
+    /// loops are nested too deeply. The program is whatever's left, which is only a guess at
+    /// what was meant if there were any errors. The errors are in the order they come in the
+    /// source.
+    pub fn compile_all(src: &str) -> (Program, Vec<ParseError>) {
+        let mut src = src.as_bytes().to_vec();
+        let mut errors = Vec::new();
+        loop {
+            let error = match Self::compile_bytes(&src) {
+                Ok(program) => {
+                    // an unclosed bracket isn't found until the end of its loop
+                    errors.sort_by_key(ParseError::position);
+                    return (program, errors);
                 }
-                StackOperation::Swap => {
-                    let right = stack.pop().ok_or(FxytError::StackEmpty)?;
-                    let left = stack.pop().ok_or(FxytError::StackEmpty)?;
-                    stack.push(right);
-                    stack.push(left);
+                Err(error) => error,
+            };
+            let skipped = match &error {
+                ParseError::InvalidCharacter(span) => span.clone(),
+                ParseError::BracketMismatch(pos) => *pos..pos + 1,
+                ParseError::LiteralTooLarge(pos) => {
+                    let digits = src[pos + 1..]
+                        .iter()
+                        .take_while(|c| matches!(c, b'0'..=b'9' | b'A'..=b'F'))
+                        .count();
+                    *pos..pos + 1 + digits
                 }
-                StackOperation::Rotate => {
-                    let top = stack.pop().ok_or(FxytError::StackEmpty)?;
-                    let second = stack.pop().ok_or(FxytError::StackEmpty)?;
-                    let third = stack.pop().ok_or(FxytError::StackEmpty)?;
-                    stack.extend_from_slice(&[second, top, third])
+                // there's no telling which loop to give up on
+                ParseError::LoopNesting => 0..src.len(),
+            };
+            // the same length, so positions still line up with the source
+            src[skipped].fill(b' ');
+            errors.push(error);
+        }
+    }
+
+    /// Like [`Program::compile`], but for a program stored as raw bytes.
+    pub fn compile_bytes(src: &[u8]) -> Result<Program, ParseError> {
+        let mut positions = Vec::new();
+        let commands = parse(src, 0, 0, &mut positions)?.1;
+        Ok(Program {
+            commands,
+            positions,
+        })
+    }
+
+    /// Whether the program's output can change with `T`, and so renders to more than one
+    /// frame.
+    ///
+    /// A `T` that only feeds commands that can't fail, like comparisons, bitwise operations and
+    /// stack shuffling, and never reaches the colour, doesn't count: `TN5>P XY^` is a still
+    /// image. One that reaches arithmetic, `F`, a loop count or anything else that could fail
+    /// does, since whether it fails could depend on the frame. So a program like `TN2*P XY^`
+    /// still counts as animated, even though every frame comes out the same.
+    pub fn is_animated(&self) -> bool {
+        self.is_animated_with_alpha(false)
+    }
+
+    /// [`Program::is_animated`], but when rendering with `alpha`, a `T` that ends up in the
+    /// fourth value from the top counts too.
+    fn is_animated_with_alpha(&self, alpha: bool) -> bool {
+        let channels = if alpha { 4 } else { 3 };
+        let mut stack = Vec::new();
+        match trace_t(&self.commands, &mut stack) {
+            Some(()) => stack.iter().rev().take(channels).any(|&from_t| from_t),
+            None => true,
+        }
+    }
+
+    /// See [`format()`].
+    pub fn format(&self) -> String {
+        let mut formatted = String::new();
+        format_commands(&mut formatted, &self.commands, 0);
+        formatted
+    }
+
+    /// See [`validate`].
+    pub fn info(&self) -> ProgramInfo {
+        let mut info = ProgramInfo {
+            animated: self.is_animated(),
+            ..Default::default()
+        };
+        for (command, depth) in self.iter() {
+            info.command_count += 1;
+            match command {
+                Command::Loop(_) | Command::While(_) | Command::Conditional(_) => {
+                    info.max_nesting = info.max_nesting.max(depth + 1);
                 }
-            },
-            Command::Loop(inner_commands) => {
-                let mut loop_counter = stack.pop().ok_or(FxytError::StackEmpty)?;
-                while loop_counter > 0 {
-                    if let Some(colour) =
-                        render_to_stack(inner_commands, stack, mode, frame_interval, coords)?
+                Command::Debug => info.has_debug = true,
+                Command::FrameInterval => info.has_frame_interval = true,
+                _ => {}
+            }
+        }
+        find_unreachable(&self.commands, 0, &self.positions, &mut info.warnings);
+        info
+    }
+
+    /// Every command in the program in the order it's written, each with how many loops,
+    /// `?[...]` or `L[...]` it's inside. A loop comes before its body, and is only visited
+    /// once however many times it would run.
+    pub fn iter(&self) -> impl Iterator<Item = (&Command, usize)> + '_ {
+        let mut levels = vec![self.commands.iter()];
+        core::iter::from_fn(move || loop {
+            let depth = levels.len().checked_sub(1)?;
+            match levels[depth].next() {
+                Some(command) => {
+                    if let Command::Loop(inner_commands)
+                    | Command::While(inner_commands)
+                    | Command::Conditional(inner_commands) = command
                     {
-                        return Ok(Some(colour));
+                        levels.push(inner_commands.iter());
                     }
-                    loop_counter -= 1;
+                    return Some((command, depth));
+                }
+                None => {
+                    levels.pop();
                 }
             }
-            Command::FrameInterval => {
-                *frame_interval = stack.pop().ok_or(FxytError::StackEmpty)?;
-            }
-            Command::Debug => {
-                eprintln!("{coords} -> {:?}", stack);
-                return Err(FxytError::DebugHalt);
+        })
+    }
+
+    /// Calls `f` on every command in the program, in the same order as [`Program::iter`]. A
+    /// loop is passed to `f` before its body, so if `f` replaces the body, the new one is
+    /// what gets visited. If `f` adds or removes commands anywhere, errors from the program
+    /// won't say where in the source they came from any more.
+    ///
+    /// If `f` nests loops deeper than the parser allows, the program is left as it was and
+    /// this fails with [`ParseError::LoopNesting`]. Commands the parser could never produce,
+    /// like a register past 3, make rendering fail rather than this.
+    pub fn visit_mut(&mut self, mut f: impl FnMut(&mut Command)) -> Result<(), ParseError> {
+        fn visit(commands: &mut [Command], f: &mut impl FnMut(&mut Command)) {
+            for command in commands {
+                f(command);
+                if let Command::Loop(inner_commands)
+                | Command::While(inner_commands)
+                | Command::Conditional(inner_commands) = command
+                {
+                    visit(inner_commands, f);
+                }
             }
         }
-        if stack.len() > 8 {
-            return Err(FxytError::StackOverflow);
+
+        let shape = |program: &Program| -> Vec<Option<usize>> {
+            program
+                .iter()
+                .map(|(command, _)| match command {
+                    Command::Loop(inner_commands)
+                    | Command::While(inner_commands)
+                    | Command::Conditional(inner_commands) => Some(inner_commands.len()),
+                    _ => None,
+                })
+                .collect()
+        };
+        let original = self.clone();
+        let before = shape(self);
+        visit(&mut self.commands, &mut f);
+        if self.iter().any(|(command, depth)| {
+            depth >= MAX_NESTING as usize
+                && matches!(
+                    command,
+                    Command::Loop(_) | Command::While(_) | Command::Conditional(_)
+                )
+        }) {
+            *self = original;
+            return Err(ParseError::LoopNesting);
         }
-        if *mode > 2 {
-            return Err(FxytError::ModeOutOfRange);
+        // positions are matched up with commands by order alone
+        if shape(self) != before {
+            self.positions.clear();
         }
+        Ok(())
     }
 
-    Ok(None)
-}
+    /// Folds runs of constant arithmetic like `N2N3*` into single pushes, so they aren't
+    /// worked out again for every pixel. Rendered with the same `options`, the program comes
+    /// out exactly as before, errors included: anything that would overflow, divide by zero,
+    /// or push the stack past [`max_stack`](RenderOptions::max_stack) is left alone. Folded
+    /// pushes are reported at the position of the first command that went into them.
+    pub fn optimize(&mut self, options: &RenderOptions) {
+        let mut positions = Vec::with_capacity(self.positions.len());
+        self.commands = fold_constants(
+            &self.commands,
+            Some(0),
+            options.max_stack,
+            &mut self.positions.iter(),
+            &mut positions,
+        );
+        self.positions = positions;
+    }
 
-fn parse(program: &str, offset: usize, nesting: u8) -> Result<(usize, Vec<Command>), ParseError> {
-    let mut parsed = Vec::with_capacity(program.len());
-    let mut unparsed = program.chars().skip(offset);
+    /// See [`render`].
+    pub fn render(&self) -> Result<Vec<Frame>, FxytError> {
+        self.render_with(&RenderOptions::default())
+    }
 
-    let mut index = offset;
-    while let Some(c) = unparsed.next() {
-        if !c.is_ascii() {
-            return Err(ParseError::InvalidCharacter(index));
-        }
+    /// See [`render_with`].
+    pub fn render_with(&self, options: &RenderOptions) -> Result<Vec<Frame>, FxytError> {
+        render_all(self, options)
+    }
 
-        let next_command = match c.to_ascii_uppercase() {
-            'X' | 'Y' | 'T' => Command::Coordinates(match c {
-                'X' => Coordinates::X,
-                'Y' => Coordinates::Y,
-                'T' => Coordinates::T,
-                _ => unreachable!(),
-            }),
-            'N' => Command::Integer,
-            d if d.is_ascii_digit() => Command::Digit(d.to_digit(10).unwrap() as u8),
-            '+' | '-' | '*' | '/' | '%' => Command::Arithmetic(match c {
-                '+' => Arithmetic::Plus,
-                '-' => Arithmetic::Minus,
-                '*' => Arithmetic::Times,
-                '/' => Arithmetic::Divide,
-                '%' => Arithmetic::Modulus,
-                _ => unreachable!(),
-            }),
-            'M' => Command::Mode,
-            '=' | '<' | '>' => Command::Comparison(match c {
-                '=' => Comparison::Equals,
-                '<' => Comparison::LessThan,
-                '>' => Comparison::GreaterThan,
-                _ => unreachable!(),
-            }),
-            '!' => Command::Invert,
-            '^' | '&' | '|' => Command::Bitwise(match c {
-                '^' => Bitwise::Xor,
-                '&' => Bitwise::And,
-                '|' => Bitwise::Or,
-                _ => unreachable!(),
-            }),
-            'C' => Command::Clip,
-            'D' | 'P' | 'S' | 'R' => Command::StackOperation(match c {
-                'D' => StackOperation::Duplicate,
-                'P' => StackOperation::Pop,
-                'S' => StackOperation::Swap,
-                'R' => StackOperation::Rotate,
-                _ => unreachable!(),
-            }),
-            '[' => {
-                if nesting >= 8 {
-                    return Err(ParseError::LoopNesting);
-                } else {
-                    let (eaten, loop_body) = parse(program, index + 1, nesting + 1)?;
-                    index += eaten;
-                    unparsed.nth(eaten - 1);
+    /// See [`render_with_stats`].
+    pub fn render_with_stats(
+        &self,
+        options: &RenderOptions,
+    ) -> Result<(Vec<Frame>, Stats), FxytError> {
+        let mut stats = Stats::default();
+        let gamma_table = options_gamma_table(options);
+        let frames = frame_times::<RGB8>(self, options)
+            .into_iter()
+            .map(|t| {
+                let frame = render_canvas_serial(
+                    self,
+                    (0, 0),
+                    options.width,
+                    options.height,
+                    t,
+                    options,
+                    Some(&mut stats),
+                )?;
+                Ok(encode_gamma(frame, gamma_table.as_ref()))
+            })
+            .collect::<Result<_, FxytError>>()?;
+        Ok((frames, stats))
+    }
 
-                    Command::Loop(loop_body)
-                }
-            }
-            ']' => {
-                if nesting > 0 {
-                    return Ok((index - offset + 1, parsed));
-                } else {
-                    return Err(ParseError::InvalidCharacter(index));
-                }
+    /// See [`render_with_progress`].
+    pub fn render_with_progress(
+        &self,
+        progress: &mut dyn FnMut(usize, usize),
+    ) -> Result<Vec<Frame>, FxytError> {
+        let frames = frame_iter(self, &RenderOptions::default());
+        let total = frames.len();
+        frames
+            .enumerate()
+            .map(|(index, frame)| {
+                let frame = frame?;
+                progress(index + 1, total);
+                Ok(frame)
+            })
+            .collect()
+    }
+
+    /// See [`render_rgba8`].
+    pub fn render_rgba8(&self) -> Result<Vec<Frame<RGBA8>>, FxytError> {
+        render_all(self, &RenderOptions::default())
+    }
+
+    /// See [`render_sized`].
+    pub fn render_sized(&self, width: usize, height: usize) -> Result<Vec<Frame>, FxytError> {
+        self.render_with(&RenderOptions::new().size(width, height))
+    }
+
+    /// See [`render_frame`].
+    pub fn render_frame(&self, t: usize) -> Result<Frame, FxytError> {
+        render_canvas(self, (0, 0), 256, 256, t, &RenderOptions::default())
+    }
+
+    /// See [`render_region`].
+    pub fn render_region(
+        &self,
+        x0: usize,
+        y0: usize,
+        width: usize,
+        height: usize,
+        t: usize,
+    ) -> Result<Frame, FxytError> {
+        render_canvas(self, (x0, y0), width, height, t, &RenderOptions::default())
+    }
+
+    /// See [`render_tiles`].
+    pub fn render_tiles(
+        &self,
+        width: usize,
+        height: usize,
+        tile: usize,
+        t: usize,
+        callback: &mut dyn FnMut(usize, usize, Frame),
+    ) -> Result<(), FxytError> {
+        let tile = tile.max(1);
+        for y0 in (0..height).step_by(tile) {
+            for x0 in (0..width).step_by(tile) {
+                let frame =
+                    self.render_region(x0, y0, tile.min(width - x0), tile.min(height - y0), t)?;
+                callback(x0, y0, frame);
             }
-            'F' => Command::FrameInterval,
-            'W' => Command::Debug,
+        }
+        Ok(())
+    }
 
-            _ => return Err(ParseError::InvalidCharacter(index)),
-        };
+    /// See [`render_frames`].
+    pub fn render_frames(&self) -> impl Iterator<Item = Result<Frame, FxytError>> + '_ {
+        frame_iter(self, &RenderOptions::default())
+    }
 
-        index += 1;
+    /// Like [`Program::render_frames`], but with non-default [`RenderOptions`]. How many
+    /// frames there will be is known up front.
+    pub fn render_frames_with(
+        &self,
+        options: &RenderOptions,
+    ) -> impl ExactSizeIterator<Item = Result<Frame, FxytError>> + '_ {
+        frame_iter(self, options)
+    }
 
-        parsed.push(next_command);
+    /// See [`render_pixel`].
+    pub fn render_pixel(&self, x: usize, y: usize, t: usize) -> Result<RGB8, FxytError> {
+        render_to_pixel(
+            self,
+            &mut Vec::new(),
+            &mut None,
+            Coords::new(x, y, t),
+            &RenderOptions::default(),
+            None,
+        )
     }
 
-    if nesting > 0 {
-        return Err(ParseError::BracketMismatch(offset));
+    /// See [`render_row`].
+    pub fn render_row(&self, y: usize, t: usize) -> Result<[RGB8; 256], FxytError> {
+        let options = RenderOptions::default();
+        let mut stack = Vec::with_capacity(stack_capacity(&options));
+        let mut frame_interval = None;
+
+        let mut row = [RGB8::default(); 256];
+        for (x, pixel) in row.iter_mut().enumerate() {
+            let coords = Coords::new(x, y, t);
+            *pixel = render_to_pixel(
+                self,
+                &mut stack,
+                &mut frame_interval,
+                coords,
+                &options,
+                None,
+            )?;
+        }
+
+        Ok(row)
     }
 
-    Ok((index - offset, parsed))
+    /// See [`render_column`].
+    pub fn render_column(&self, x: usize, t: usize) -> Result<[RGB8; 256], FxytError> {
+        let options = RenderOptions::default();
+        let mut stack = Vec::with_capacity(stack_capacity(&options));
+        let mut frame_interval = None;
+
+        let mut column = [RGB8::default(); 256];
+        for (y, pixel) in column.iter_mut().enumerate() {
+            let coords = Coords::new(x, y, t);
+            *pixel = render_to_pixel(
+                self,
+                &mut stack,
+                &mut frame_interval,
+                coords,
+                &options,
+                None,
+            )?;
+        }
+
+        Ok(column)
+    }
 }
 
-#[derive(Copy, Clone, Debug)]
-pub struct Frame {
-    pub interval: isize,
-    pub image: [[RGB8; 256]; 256],
+/// Same as [`Program::compile`].
+///
+/// ```
+/// # fn main() -> Result<(), fxyt::FxytError> {
+/// let program: fxyt::Program = "XY^ D D".parse()?;
+/// let frames = program.render()?;
+/// assert_eq!(frames[0].image[255][3], rgb::RGB8::new(3, 3, 3));
+/// # Ok(())
+/// # }
+/// ```
+impl FromStr for Program {
+    type Err = ParseError;
+
+    fn from_str(src: &str) -> Result<Self, Self::Err> {
+        Program::compile(src)
+    }
 }
 
-#[derive(Clone, PartialEq, Eq, Debug)]
-enum Command {
-    Coordinates(Coordinates),
-    Integer,
-    Digit(u8),
-    Arithmetic(Arithmetic),
-    Mode,
-    Comparison(Comparison),
-    Invert,
-    Bitwise(Bitwise),
-    Clip,
-    StackOperation(StackOperation),
-    Loop(Vec<Command>),
-    FrameInterval,
-    Debug,
+/// Renders three programs together, one for each colour channel, instead of one program
+/// that leaves all three on its stack. Each runs on its own at every pixel, with a fresh stack
+/// and mode, and the value it leaves on top of the stack (0 if there isn't one) is its
+/// channel. The result is animated if any of the three are.
+pub fn render_channels(red: &str, green: &str, blue: &str) -> Result<Vec<Frame>, FxytError> {
+    let channels = [
+        Program::compile(red)?,
+        Program::compile(green)?,
+        Program::compile(blue)?,
+    ];
+    let options = RenderOptions::default();
+    let frame_count = if channels.iter().any(Program::is_animated) {
+        256
+    } else {
+        1
+    };
+
+    let (width, height) = (options.width, options.height);
+    let mut stack = Vec::with_capacity(stack_capacity(&options));
+    (0..frame_count)
+        .map(|t| {
+            let mut canvas = vec![vec![RGB8::default(); width]; height];
+            let mut frame_interval = None;
+            #[allow(clippy::needless_range_loop)] // x picks a column, not one of canvas' rows
+            for x in 0..width {
+                for y in 0..height {
+                    let coords = Coords::new(x, y, t);
+                    let mut values = [0; 3];
+                    for (channel, (program, value)) in channels.iter().zip(&mut values).enumerate()
+                    {
+                        *value = render_channel(
+                            program,
+                            channel,
+                            &mut stack,
+                            &mut frame_interval,
+                            coords,
+                            &options,
+                        )?;
+                    }
+
+                    let [red, green, blue] = values;
+                    if !values.iter().all(|value| (0..=255).contains(value)) {
+                        return Err(FxytError::RgbOutOfRange {
+                            coords,
+                            red,
+                            green,
+                            blue,
+                            alpha: None,
+                        });
+                    }
+                    canvas[row_of(y, height, &options)][x] =
+                        RGB8::new(red as u8, green as u8, blue as u8);
+                }
+            }
+            Ok(Frame {
+                interval: frame_interval.unwrap_or(100),
+                custom_interval: frame_interval.is_some(),
+                image: canvas,
+            })
+        })
+        .collect()
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
-enum Coordinates {
-    X,
-    Y,
-    T,
+fn frame_iter<P: Borrow<Program>, C: Colour>(
+    program: P,
+    options: &RenderOptions,
+) -> impl ExactSizeIterator<Item = Result<Frame<C>, FxytError>> {
+    let times = frame_times::<C>(program.borrow(), options);
+    let options = options.clone();
+    let gamma_table = options_gamma_table(&options);
+
+    times.into_iter().map(move |t| {
+        let frame = render_canvas(
+            program.borrow(),
+            (0, 0),
+            options.width,
+            options.height,
+            t,
+            &options,
+        )?;
+        Ok(encode_gamma(frame, gamma_table.as_ref()))
+    })
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
-enum Arithmetic {
-    Plus,
-    Minus,
-    Times,
-    Divide,
-    Modulus,
+/// Renders every frame at once, the same as collecting [`frame_iter`].
+#[cfg(not(feature = "parallel"))]
+fn render_all<C: Colour>(
+    program: &Program,
+    options: &RenderOptions,
+) -> Result<Vec<Frame<C>>, FxytError> {
+    frame_iter(program, options).collect()
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
-enum Comparison {
-    Equals,
-    LessThan,
-    GreaterThan,
+/// Renders every frame at once. With enough frames to keep every thread busy, whole frames
+/// are handed out to threads, which needs far less coordination than splitting up each frame
+/// in turn.
+#[cfg(feature = "parallel")]
+fn render_all<C: Colour>(
+    program: &Program,
+    options: &RenderOptions,
+) -> Result<Vec<Frame<C>>, FxytError> {
+    if frame_times::<C>(program, options).len() >= rayon::current_num_threads() {
+        render_frames_parallel(program, options)
+    } else {
+        frame_iter(program, options).collect()
+    }
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
-enum Bitwise {
-    Xor,
-    And,
-    Or,
+/// Renders each frame on its own rayon task, with its pixels one after another. If several
+/// frames fail, which of their errors is returned isn't specified.
+#[cfg(feature = "parallel")]
+fn render_frames_parallel<C: Colour>(
+    program: &Program,
+    options: &RenderOptions,
+) -> Result<Vec<Frame<C>>, FxytError> {
+    use rayon::prelude::*;
+
+    let gamma_table = options_gamma_table(options);
+    frame_times::<C>(program, options)
+        .into_par_iter()
+        .map(|t| {
+            let frame = render_canvas_serial(
+                program,
+                (0, 0),
+                options.width,
+                options.height,
+                t,
+                options,
+                None,
+            )?;
+            Ok(encode_gamma(frame, gamma_table.as_ref()))
+        })
+        .collect()
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
-enum StackOperation {
-    Duplicate,
-    Pop,
-    Swap,
-    Rotate,
+/// The value of `T` in each frame, in the order they're shown.
+fn frame_times<C: Colour>(program: &Program, options: &RenderOptions) -> Vec<usize> {
+    let frame_count = if program.is_animated_with_alpha(C::ALPHA) {
+        options.frames.unwrap_or(256)
+    } else {
+        1
+    };
+
+    (0..frame_count)
+        .map(|frame| {
+            // wrapping, like converting a `T` too large for an `isize` does
+            options.t_start.wrapping_add(if options.seamless {
+                // evens on the way up, odds on the way down, so every T is still visited
+                // once and neighbouring frames (including last -> first) are only 1 apart
+                if frame < frame_count.div_ceil(2) {
+                    frame * 2
+                } else {
+                    (frame_count - 1 - frame) * 2 + 1
+                }
+            } else {
+                frame
+            })
+        })
+        .collect()
 }
 
-#[derive(Clone, Copy)]
-struct Coords {
-    x: isize,
-    y: isize,
-    t: isize,
+/// The table for [`RenderOptions::gamma`], if it's set.
+#[cfg(feature = "std")]
+fn options_gamma_table(options: &RenderOptions) -> Option<[u8; 256]> {
+    options.gamma.filter(|&gamma| gamma > 0.0).map(gamma_table)
 }
 
-impl Coords {
-    fn new(x: usize, y: usize, t: usize) -> Self {
-        Self {
-            x: x as isize,
-            y: y as isize,
-            t: t as isize,
-        }
+#[cfg(not(feature = "std"))]
+fn options_gamma_table(_options: &RenderOptions) -> Option<[u8; 256]> {
+    None
+}
+
+fn encode_gamma<C: Colour>(frame: Frame<C>, gamma_table: Option<&[u8; 256]>) -> Frame<C> {
+    match gamma_table {
+        Some(table) => frame.encode(table),
+        None => frame,
     }
 }
 
-impl Display for Coords {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "({}, {}, {})", self.x, self.y, self.t)
+/// Maps each linear channel value to its gamma encoded equivalent.
+#[cfg(feature = "std")]
+fn gamma_table(gamma: f32) -> [u8; 256] {
+    core::array::from_fn(|value| (255.0 * (value as f32 / 255.0).powf(1.0 / gamma)).round() as u8)
+}
+
+/// Which row of the canvas the pixels at `y` go in.
+fn row_of(y: usize, height: usize, options: &RenderOptions) -> usize {
+    if options.y_down {
+        y
+    } else {
+        height - 1 - y
     }
 }
 
-#[derive(Error, Debug)]
-pub enum FxytError {
-    #[error("RGB value greater than 255 or less than 0")]
-    RgbOutOfRange,
-    #[error("Attempt to push more than 8 values to the stack")]
-    StackOverflow,
-    #[error("Attempt to read from an empty stack")]
-    StackEmpty,
-    #[error("Attempt to divide by zero in mode 0")]
-    DivideByZero,
-    #[error("Attempt to increment mode beyond 2")]
-    ModeOutOfRange,
-    #[error("Failed to parse command")]
-    Parse(#[from] ParseError),
-    #[error("Debug command executed, output halted")]
-    DebugHalt,
+#[cfg(not(feature = "parallel"))]
+fn render_canvas<C: Colour>(
+    program: &Program,
+    origin: (usize, usize),
+    width: usize,
+    height: usize,
+    t: usize,
+    options: &RenderOptions,
+) -> Result<Frame<C>, FxytError> {
+    render_canvas_serial(program, origin, width, height, t, options, None)
+}
+
+/// Renders the pixels one after another, starting from the bottom left, counting what runs
+/// into `stats` if it's there.
+fn render_canvas_serial<C: Colour>(
+    program: &Program,
+    (x0, y0): (usize, usize),
+    width: usize,
+    height: usize,
+    t: usize,
+    options: &RenderOptions,
+    mut stats: Option<&mut Stats>,
+) -> Result<Frame<C>, FxytError> {
+    let mut canvas = vec![vec![C::default(); width]; height];
+
+    let mut stack = Vec::with_capacity(stack_capacity(options));
+    let mut frame_interval = None;
+
+    #[allow(clippy::needless_range_loop)] //this is cleaner than what clippy wants
+    for x in 0..width {
+        for y in 0..height {
+            canvas[row_of(y, height, options)][x] = render_sampled(
+                program,
+                &mut stack,
+                &mut frame_interval,
+                Coords::new(x0 + x, y0 + y, t),
+                options,
+                stats.as_deref_mut(),
+            )?;
+        }
+    }
+
+    Ok(Frame {
+        interval: frame_interval.unwrap_or(100),
+        custom_interval: frame_interval.is_some(),
+        image: canvas,
+    })
+}
+
+/// Renders each column on its own rayon task. If several pixels fail, which of their errors
+/// is returned isn't specified.
+#[cfg(feature = "parallel")]
+fn render_canvas<C: Colour>(
+    program: &Program,
+    (x0, y0): (usize, usize),
+    width: usize,
+    height: usize,
+    t: usize,
+    options: &RenderOptions,
+) -> Result<Frame<C>, FxytError> {
+    use rayon::prelude::*;
+
+    let columns = (0..width)
+        .into_par_iter()
+        .map(|x| {
+            let mut column = vec![C::default(); height];
+            let mut stack = Vec::with_capacity(stack_capacity(options));
+            let mut column_interval = None;
+            for (y, pixel) in column.iter_mut().enumerate() {
+                let coords = Coords::new(x0 + x, y0 + y, t);
+                *pixel = render_sampled(
+                    program,
+                    &mut stack,
+                    &mut column_interval,
+                    coords,
+                    options,
+                    None,
+                )?;
+            }
+            Ok((column, column_interval))
+        })
+        .collect::<Result<Vec<_>, FxytError>>()?;
+
+    let mut canvas = vec![vec![C::default(); width]; height];
+    let mut frame_interval = None;
+    for (x, (column, column_interval)) in columns.into_iter().enumerate() {
+        for (y, pixel) in column.into_iter().enumerate() {
+            canvas[row_of(y, height, options)][x] = pixel;
+        }
+        // same as the serial path: the last pixel to run F decides the interval
+        frame_interval = column_interval.or(frame_interval);
+    }
+
+    Ok(Frame {
+        interval: frame_interval.unwrap_or(100),
+        custom_interval: frame_interval.is_some(),
+        image: canvas,
+    })
+}
+
+/// How many times one `L[...]` loop may run before it's assumed to be stuck.
+const WHILE_LIMIT: usize = 4096;
+
+/// Room for the values a program may leave on the stack, plus the two `'D` can push past it, up
+/// to a point: a stack that's allowed to get very deep rarely does.
+fn stack_capacity(options: &RenderOptions) -> usize {
+    options.max_stack.min(256) + 2
+}
+
+/// How many loops, `?[...]` and `L[...]` can be nested inside each other.
+const MAX_NESTING: u8 = 8;
+
+/// How many registers `:n` and `;n` can address.
+const REGISTER_COUNT: usize = 4;
+
+/// What a program can change as it runs, other than the stack, which starts afresh for every
+/// pixel.
+#[derive(Default)]
+struct PixelState<'a> {
+    mode: u8,
+    /// How many more commands may run before the budget is spent.
+    ops: u64,
+    registers: [isize; REGISTER_COUNT],
+    /// Where to count the commands that run, if anything is counting them. Unlike the rest,
+    /// this carries on from one pixel to the next.
+    stats: Option<&'a mut Stats>,
+}
+
+/// Renders the pixel at `coords`, or with supersampling, the average of the pixels that cover
+/// it on the finer grid.
+fn render_sampled<C: Colour>(
+    program: &Program,
+    stack: &mut Vec<isize>,
+    frame_interval: &mut Option<u32>,
+    coords: Coords,
+    options: &RenderOptions,
+    mut stats: Option<&mut Stats>,
+) -> Result<C, FxytError> {
+    let samples = options.samples.max(1) as isize;
+    if samples == 1 {
+        return render_to_pixel(program, stack, frame_interval, coords, options, stats);
+    }
+
+    let mut sums = [0u32; 4];
+    for dx in 0..samples {
+        for dy in 0..samples {
+            // wrapping, like converting coordinates too large for an `isize` does
+            let sample = Coords {
+                x: coords.x.wrapping_mul(samples).wrapping_add(dx),
+                y: coords.y.wrapping_mul(samples).wrapping_add(dy),
+                ..coords
+            };
+            let colour: C = render_to_pixel(
+                program,
+                stack,
+                frame_interval,
+                sample,
+                options,
+                stats.as_deref_mut(),
+            )?;
+            let RGBA8 { r, g, b, a } = colour.rgba();
+            for (sum, channel) in sums.iter_mut().zip([r, g, b, a]) {
+                *sum += channel as u32;
+            }
+        }
+    }
+
+    let count = (samples * samples) as u32;
+    let [r, g, b, a] = sums.map(|sum| ((sum + count / 2) / count) as u8);
+    Ok(C::new(RGB8::new(r, g, b), a))
+}
+
+/// `stack` is only there so its allocation can be reused from one pixel to the next; it's
+/// cleared before the program runs.
+fn render_to_pixel<C: Colour>(
+    program: &Program,
+    stack: &mut Vec<isize>,
+    frame_interval: &mut Option<u32>,
+    coords: Coords,
+    options: &RenderOptions,
+    stats: Option<&mut Stats>,
+) -> Result<C, FxytError> {
+    stack.clear();
+    let mut state = PixelState {
+        ops: options.max_ops.unwrap_or(u64::MAX),
+        stats,
+        ..PixelState::default()
+    };
+
+    let colour = render_to_stack(
+        &program.commands,
+        stack,
+        &mut state,
+        frame_interval,
+        coords,
+        options,
+    )
+    .map_err(|fault| fault.locate(program, coords))?;
+    if let Some(stats) = state.stats {
+        // the last command's depth, which no command after it saw
+        stats.max_depth = stats.max_depth.max(stack.len());
+    }
+    if let Some(colour) = colour {
+        return Ok(C::new(colour, 255));
+    }
+
+    if let (ColourMode::Grayscale, &[grey]) = (options.colours, stack.as_slice()) {
+        stack.extend_from_slice(&[grey, grey]);
+    }
+
+    // alpha is only read if there's a value left over for it
+    let alpha = if C::ALPHA && stack.len() > 3 {
+        stack.pop()
+    } else {
+        None
+    };
+    let blue = stack.pop().unwrap_or_default();
+    let green = stack.pop().unwrap_or_default();
+    let red = stack.pop().unwrap_or_default();
+
+    if [red, green, blue, alpha.unwrap_or(255)]
+        .iter()
+        .any(|channel| !(0..=255).contains(channel))
+    {
+        return Err(FxytError::RgbOutOfRange {
+            coords,
+            red,
+            green,
+            blue,
+            alpha,
+        });
+    }
+
+    let colour = match options.colours {
+        ColourMode::Rgb | ColourMode::Grayscale => RGB8::new(red as u8, green as u8, blue as u8),
+        ColourMode::Hsv => hsv_to_rgb(red, green, blue),
+    };
+    Ok(C::new(colour, alpha.unwrap_or(255) as u8))
+}
+
+/// Runs one of [`render_channels`]' programs at `coords`, giving the value it leaves for
+/// `channel`: 0 for red, 1 for green or 2 for blue.
+fn render_channel(
+    program: &Program,
+    channel: usize,
+    stack: &mut Vec<isize>,
+    frame_interval: &mut Option<u32>,
+    coords: Coords,
+    options: &RenderOptions,
+) -> Result<isize, FxytError> {
+    stack.clear();
+    let mut state = PixelState {
+        ops: options.max_ops.unwrap_or(u64::MAX),
+        ..PixelState::default()
+    };
+
+    let colour = render_to_stack(
+        &program.commands,
+        stack,
+        &mut state,
+        frame_interval,
+        coords,
+        options,
+    )
+    .map_err(|fault| fault.locate(program, coords))?;
+    Ok(match colour {
+        // it stopped early with a whole colour, like dividing by zero in mode 1 or 2
+        Some(colour) => [colour.r, colour.g, colour.b][channel] as isize,
+        None => stack.pop().unwrap_or_default(),
+    })
+}
+
+/// The pixel types a frame can be rendered to.
+trait Colour: Copy + Default + Send {
+    /// Whether a fourth value can be read off the stack for alpha.
+    const ALPHA: bool;
+
+    fn new(colour: RGB8, alpha: u8) -> Self;
+
+    /// Looks each colour channel up in `table`, leaving alpha alone.
+    fn map_channels(self, table: &[u8; 256]) -> Self;
+
+    /// The colour with its alpha, which is opaque if there isn't any.
+    fn rgba(self) -> RGBA8;
+}
+
+impl Colour for RGB8 {
+    const ALPHA: bool = false;
+
+    fn new(colour: RGB8, _alpha: u8) -> Self {
+        colour
+    }
+
+    fn map_channels(self, table: &[u8; 256]) -> Self {
+        RGB8::new(
+            table[self.r as usize],
+            table[self.g as usize],
+            table[self.b as usize],
+        )
+    }
+
+    fn rgba(self) -> RGBA8 {
+        self.with_alpha(255)
+    }
+}
+
+impl Colour for RGBA8 {
+    const ALPHA: bool = true;
+
+    fn new(colour: RGB8, alpha: u8) -> Self {
+        RGBA8::new(colour.r, colour.g, colour.b, alpha)
+    }
+
+    fn map_channels(self, table: &[u8; 256]) -> Self {
+        Colour::new(self.rgb().map_channels(table), self.a)
+    }
+
+    fn rgba(self) -> RGBA8 {
+        self
+    }
+}
+
+/// Integer HSV to RGB conversion, with every component in `0..=255` and hue covering the
+/// whole colour wheel.
+fn hsv_to_rgb(hue: isize, saturation: isize, value: isize) -> RGB8 {
+    // six sectors of the wheel, each split into 256 steps
+    let sector = hue * 6 / 256;
+    let step = hue * 6 % 256;
+    let low = value * (255 - saturation) / 255;
+    let falling = value * (255 - saturation * step / 255) / 255;
+    let rising = value * (255 - saturation * (255 - step) / 255) / 255;
+
+    let (red, green, blue) = match sector {
+        0 => (value, rising, low),
+        1 => (falling, value, low),
+        2 => (low, value, rising),
+        3 => (low, falling, value),
+        4 => (rising, low, value),
+        _ => (value, low, falling),
+    };
+    RGB8::new(red as u8, green as u8, blue as u8)
+}
+
+/// A command that failed, and which one it was.
+struct Fault {
+    error: RuntimeError,
+    /// The failed command's index within the block that passed the fault on, counting
+    /// commands in the order [`Program`] keeps their positions. `None` while it's still in the
+    /// command that raised it.
+    command: Option<usize>,
+}
+
+impl From<RuntimeError> for Fault {
+    fn from(error: RuntimeError) -> Self {
+        Fault {
+            error,
+            command: None,
+        }
+    }
+}
+
+impl Fault {
+    /// The error for a fault that reached the top of `program`, running at `coords`.
+    fn locate(self, program: &Program, coords: Coords) -> FxytError {
+        FxytError::Runtime {
+            error: self.error,
+            coords,
+            pos: self
+                .command
+                .and_then(|command| program.positions.get(command).copied()),
+        }
+    }
+}
+
+/// The greatest common divisor of `left` and `right`, ignoring their signs, or `None` if it
+/// doesn't fit in an `isize`. That's only for `isize::MIN` with itself or 0.
+fn gcd(left: isize, right: isize) -> Option<isize> {
+    let (mut left, mut right) = (left.unsigned_abs(), right.unsigned_abs());
+    while right != 0 {
+        (left, right) = (right, left % right);
+    }
+    isize::try_from(left).ok()
+}
+
+/// `base` to the power of `exponent`, modulo the size of `modulus`, which mustn't be 0. The
+/// result is never negative, and nothing overflows along the way.
+fn mod_pow(base: isize, mut exponent: usize, modulus: isize) -> isize {
+    // everything stays below the modulus, which is at most 2^63, so products fit in a u128
+    let modulus = modulus.unsigned_abs() as u128;
+    let mut base = (base as i128).rem_euclid(modulus as i128) as u128;
+    let mut result = 1 % modulus;
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = result * base % modulus;
+        }
+        base = base * base % modulus;
+        exponent >>= 1;
+    }
+    result as isize
+}
+
+/// Smoothstep, `3x² - 2x³`, with `x` and the result both running from 0 to 255.
+fn smooth_step(x: isize) -> isize {
+    (x * x * (3 * 255 - 2 * x) + 255 * 255 / 2) / (255 * 255)
+}
+
+/// Cubic ease-in-out, `4x³` for the first half and mirrored for the second, with `x` and the
+/// result both running from 0 to 255.
+fn ease_in_out(x: isize) -> isize {
+    let ease_in = |x: isize| (4 * x * x * x + 255 * 255 / 2) / (255 * 255);
+    if x < 128 {
+        ease_in(x)
+    } else {
+        255 - ease_in(255 - x)
+    }
+}
+
+/// Stops the pixel the way dividing by zero does in `mode`: with `error` in mode 0, or with
+/// black or red in modes 1 and 2.
+fn divide_by_zero(mode: u8, error: RuntimeError) -> Result<Option<RGB8>, Fault> {
+    match mode {
+        0 => Err(error.into()),
+        1 => Ok(Some(RGB8::default())),
+        2 => Ok(Some(RGB8::new(255, 0, 0))),
+        _ => unreachable!(),
+    }
+}
+
+/// How many commands there are in `command`, counting itself and everything in its body.
+fn command_count(command: &Command) -> usize {
+    match command {
+        Command::Loop(inner_commands)
+        | Command::While(inner_commands)
+        | Command::Conditional(inner_commands) => {
+            1 + inner_commands.iter().map(command_count).sum::<usize>()
+        }
+        _ => 1,
+    }
+}
+
+fn render_to_stack(
+    commands: &[Command],
+    stack: &mut Vec<isize>,
+    state: &mut PixelState,
+    frame_interval: &mut Option<u32>,
+    coords: Coords,
+    options: &RenderOptions,
+) -> Result<Option<RGB8>, Fault> {
+    for (index, command) in commands.iter().enumerate() {
+        let colour = run_command(command, stack, state, frame_interval, coords, options).map_err(
+            |fault| {
+                // bodies come before their loop, so the loop itself is the last of its commands
+                let start: usize = commands[..index].iter().map(command_count).sum();
+                let within = fault.command.unwrap_or(command_count(command) - 1);
+                Fault {
+                    error: fault.error,
+                    command: Some(start + within),
+                }
+            },
+        )?;
+        if colour.is_some() {
+            return Ok(colour);
+        }
+    }
+
+    Ok(None)
+}
+
+fn run_command(
+    command: &Command,
+    stack: &mut Vec<isize>,
+    state: &mut PixelState,
+    frame_interval: &mut Option<u32>,
+    coords: Coords,
+    options: &RenderOptions,
+) -> Result<Option<RGB8>, Fault> {
+    state.ops = state
+        .ops
+        .checked_sub(1)
+        .ok_or(RuntimeError::OpBudgetExceeded)?;
+    if let Some(stats) = &mut state.stats {
+        stats.count(command, stack.len());
+    }
+    match command {
+        Command::Coordinates(c) => match c {
+            Coordinates::X => stack.push(coords.x),
+            Coordinates::Y => stack.push(coords.y),
+            Coordinates::T => stack.push(coords.t),
+        },
+        Command::Integer => stack.push(0),
+        Command::Literal(value) => stack.push(*value),
+        Command::Digit(d) => {
+            let top = stack.pop().ok_or(RuntimeError::StackEmpty)?;
+            let appended = top
+                .checked_mul(10)
+                .and_then(|top| top.checked_add(*d as isize));
+            stack.push(appended.ok_or(RuntimeError::IntegerOverflow)?)
+        }
+        Command::Arithmetic(a) => {
+            let right = stack.pop().ok_or(RuntimeError::StackEmpty)?;
+            let left = stack.pop().ok_or(RuntimeError::StackEmpty)?;
+            if matches!(a, Arithmetic::Divide | Arithmetic::Modulus) && right == 0 {
+                return divide_by_zero(
+                    state.mode,
+                    if *a == Arithmetic::Divide {
+                        RuntimeError::DivideByZero
+                    } else {
+                        RuntimeError::ModulusByZero
+                    },
+                );
+            }
+            if *a == Arithmetic::Power && right < 0 {
+                return Err(RuntimeError::NegativeExponent.into());
+            }
+            stack.push(a.apply(left, right).ok_or(RuntimeError::IntegerOverflow)?)
+        }
+        Command::Mode => state.mode += 1,
+        Command::Comparison(c) => {
+            let right = stack.pop().ok_or(RuntimeError::StackEmpty)?;
+            let left = stack.pop().ok_or(RuntimeError::StackEmpty)?;
+            stack.push(c.apply(left, right))
+        }
+        Command::Invert => {
+            let arg = stack.pop().ok_or(RuntimeError::StackEmpty)?;
+            stack.push((arg == 0) as isize)
+        }
+        Command::Bitwise(b) => {
+            let right = stack.pop().ok_or(RuntimeError::StackEmpty)?;
+            let left = stack.pop().ok_or(RuntimeError::StackEmpty)?;
+            stack.push(b.apply(left, right))
+        }
+        Command::Clip => {
+            let arg = stack.pop().ok_or(RuntimeError::StackEmpty)?;
+            stack.push(arg.clamp(0, 255))
+        }
+        Command::SmoothStep => {
+            let arg = stack.pop().ok_or(RuntimeError::StackEmpty)?;
+            stack.push(smooth_step(arg.clamp(0, 255)))
+        }
+        Command::EaseInOut => {
+            let arg = stack.pop().ok_or(RuntimeError::StackEmpty)?;
+            stack.push(ease_in_out(arg.clamp(0, 255)))
+        }
+        Command::StackOperation(so) => match so {
+            StackOperation::Duplicate => {
+                let arg = stack.pop().ok_or(RuntimeError::StackEmpty)?;
+                stack.push(arg);
+                stack.push(arg);
+            }
+            StackOperation::DuplicatePair => {
+                let top = stack.pop().ok_or(RuntimeError::StackEmpty)?;
+                let second = stack.pop().ok_or(RuntimeError::StackEmpty)?;
+                stack.extend_from_slice(&[second, top, second, top])
+            }
+            StackOperation::Pop => {
+                stack.pop().ok_or(RuntimeError::StackEmpty)?;
+            }
+            StackOperation::Swap => {
+                let right = stack.pop().ok_or(RuntimeError::StackEmpty)?;
+                let left = stack.pop().ok_or(RuntimeError::StackEmpty)?;
+                stack.push(right);
+                stack.push(left);
+            }
+            StackOperation::Rotate => {
+                let top = stack.pop().ok_or(RuntimeError::StackEmpty)?;
+                let second = stack.pop().ok_or(RuntimeError::StackEmpty)?;
+                let third = stack.pop().ok_or(RuntimeError::StackEmpty)?;
+                stack.extend_from_slice(&[second, top, third])
+            }
+            StackOperation::RotateBack => {
+                let top = stack.pop().ok_or(RuntimeError::StackEmpty)?;
+                let second = stack.pop().ok_or(RuntimeError::StackEmpty)?;
+                let third = stack.pop().ok_or(RuntimeError::StackEmpty)?;
+                stack.extend_from_slice(&[top, third, second])
+            }
+            StackOperation::Over => {
+                let top = stack.pop().ok_or(RuntimeError::StackEmpty)?;
+                let second = stack.pop().ok_or(RuntimeError::StackEmpty)?;
+                stack.extend_from_slice(&[second, top, second])
+            }
+            StackOperation::SwapDeep | StackOperation::Roll => {
+                let depth = stack.pop().ok_or(RuntimeError::StackEmpty)?;
+                let top = stack.len().checked_sub(1).ok_or(RuntimeError::StackEmpty)?;
+                let other = usize::try_from(depth)
+                    .ok()
+                    .and_then(|depth| top.checked_sub(depth))
+                    .ok_or(RuntimeError::StackEmpty)?;
+                if *so == StackOperation::SwapDeep {
+                    stack.swap(top, other)
+                } else {
+                    stack[other..].rotate_left(1)
+                }
+            }
+        },
+        Command::Loop(inner_commands) => {
+            let mut loop_counter = stack.pop().ok_or(RuntimeError::StackEmpty)?;
+            while loop_counter > 0 {
+                // even an empty body costs something, or the budget couldn't stop it
+                state.ops = state
+                    .ops
+                    .checked_sub(1)
+                    .ok_or(RuntimeError::OpBudgetExceeded)?;
+                if let Some(colour) = render_to_stack(
+                    inner_commands,
+                    stack,
+                    state,
+                    frame_interval,
+                    coords,
+                    options,
+                )? {
+                    return Ok(Some(colour));
+                }
+                loop_counter -= 1;
+            }
+        }
+        Command::While(inner_commands) => {
+            let mut passes = 0;
+            while stack.pop().ok_or(RuntimeError::StackEmpty)? != 0 {
+                if passes == WHILE_LIMIT {
+                    return Err(RuntimeError::IterationLimit.into());
+                }
+                passes += 1;
+                state.ops = state
+                    .ops
+                    .checked_sub(1)
+                    .ok_or(RuntimeError::OpBudgetExceeded)?;
+                if let Some(colour) = render_to_stack(
+                    inner_commands,
+                    stack,
+                    state,
+                    frame_interval,
+                    coords,
+                    options,
+                )? {
+                    return Ok(Some(colour));
+                }
+            }
+        }
+        Command::Conditional(inner_commands) => {
+            let condition = stack.pop().ok_or(RuntimeError::StackEmpty)?;
+            if condition != 0 {
+                if let Some(colour) = render_to_stack(
+                    inner_commands,
+                    stack,
+                    state,
+                    frame_interval,
+                    coords,
+                    options,
+                )? {
+                    return Ok(Some(colour));
+                }
+            }
+        }
+        Command::BitReverse => {
+            let width = stack.pop().ok_or(RuntimeError::StackEmpty)?;
+            let value = stack.pop().ok_or(RuntimeError::StackEmpty)?;
+            if !(0..=isize::BITS as isize).contains(&width) {
+                return Err(RuntimeError::BitWidthOutOfRange.into());
+            }
+            stack.push(if width == 0 {
+                value
+            } else {
+                let shift = isize::BITS - width as u32;
+                let mask = (usize::MAX >> shift) as isize;
+                let reversed = ((value as usize).reverse_bits() >> shift) as isize;
+                (value & !mask) | reversed
+            })
+        }
+        Command::DigitalRoot => {
+            let arg = stack.pop().ok_or(RuntimeError::StackEmpty)?.unsigned_abs();
+            stack.push(if arg == 0 { 0 } else { 1 + (arg - 1) % 9 } as isize)
+        }
+        Command::Spiral => {
+            let twist = stack.pop().ok_or(RuntimeError::StackEmpty)?;
+            // wrapping is fine here, 2^64 is a multiple of 256 so the result is unaffected
+            let value = coords
+                .angle()
+                .wrapping_add(coords.radius().wrapping_mul(twist));
+            stack.push(value.rem_euclid(256))
+        }
+        Command::Hash => {
+            let seed = stack.pop().ok_or(RuntimeError::StackEmpty)?;
+            stack.push(coords.hash(seed))
+        }
+        Command::Store(register) => {
+            let value = stack.pop().ok_or(RuntimeError::StackEmpty)?;
+            *state
+                .registers
+                .get_mut(*register as usize)
+                .ok_or(RuntimeError::RegisterOutOfRange)? = value
+        }
+        Command::Load(register) => {
+            let value = state.registers.get(*register as usize);
+            stack.push(*value.ok_or(RuntimeError::RegisterOutOfRange)?)
+        }
+        Command::Accumulate => {
+            let arg = stack.pop().ok_or(RuntimeError::StackEmpty)?;
+            let accumulator = stack.first_mut().ok_or(RuntimeError::StackEmpty)?;
+            *accumulator = accumulator
+                .checked_add(arg)
+                .ok_or(RuntimeError::IntegerOverflow)?;
+        }
+        Command::FrameInterval => {
+            let interval = stack.pop().ok_or(RuntimeError::StackEmpty)?;
+            *frame_interval =
+                Some(u32::try_from(interval).map_err(|_| RuntimeError::FrameIntervalOutOfRange)?);
+        }
+        Command::Debug => {
+            #[cfg(feature = "std")]
+            match &options.debug_output {
+                Some(output) => {
+                    let mut output = output.lock().unwrap_or_else(PoisonError::into_inner);
+                    // failing to print debug output shouldn't stop the render
+                    let _ = writeln!(output, "{coords} -> {:?}", stack);
+                }
+                None => eprintln!("{coords} -> {:?}", stack),
+            }
+            if options.debug_halt {
+                return Err(RuntimeError::DebugHalt.into());
+            }
+        }
+        Command::Absolute => {
+            let arg = stack.pop().ok_or(RuntimeError::StackEmpty)?;
+            stack.push(arg.checked_abs().ok_or(RuntimeError::IntegerOverflow)?)
+        }
+        Command::Negate => {
+            let arg = stack.pop().ok_or(RuntimeError::StackEmpty)?;
+            stack.push(arg.checked_neg().ok_or(RuntimeError::IntegerOverflow)?)
+        }
+        Command::SquareRoot => {
+            let arg = stack.pop().ok_or(RuntimeError::StackEmpty)?;
+            if arg < 0 {
+                return Err(RuntimeError::NegativeSqrt.into());
+            }
+            stack.push(arg.isqrt())
+        }
+        Command::Clear => stack.clear(),
+        Command::Depth => stack.push(stack.len() as isize),
+        Command::ModPow => {
+            let modulus = stack.pop().ok_or(RuntimeError::StackEmpty)?;
+            let exponent = stack.pop().ok_or(RuntimeError::StackEmpty)?;
+            let base = stack.pop().ok_or(RuntimeError::StackEmpty)?;
+            if modulus == 0 {
+                return divide_by_zero(state.mode, RuntimeError::ModulusByZero);
+            }
+            if exponent < 0 {
+                return Err(RuntimeError::NegativeExponent.into());
+            }
+            stack.push(mod_pow(base, exponent as usize, modulus))
+        }
+        Command::ScaledTime => {
+            let frames = options.frames.unwrap_or(256).max(1);
+            let scaled = isize::try_from(options.t_start)
+                .ok()
+                .and_then(|t_start| coords.t.checked_sub(t_start)?.checked_mul(256))
+                .ok_or(RuntimeError::IntegerOverflow)?;
+            // more frames than there are stack values is so many that every one rounds to 0
+            stack.push(isize::try_from(frames).map_or(0, |frames| scaled / frames))
+        }
+        Command::Width | Command::Height => {
+            let size = match command {
+                Command::Width => options.width,
+                _ => options.height,
+            };
+            // on the finer grid when supersampling, so it still matches the coordinates
+            let size = size.saturating_mul(options.samples.max(1) as usize);
+            stack.push(isize::try_from(size).map_err(|_| RuntimeError::IntegerOverflow)?)
+        }
+        Command::Min | Command::Max => {
+            let right = stack.pop().ok_or(RuntimeError::StackEmpty)?;
+            let left = stack.pop().ok_or(RuntimeError::StackEmpty)?;
+            stack.push(if *command == Command::Min {
+                left.min(right)
+            } else {
+                left.max(right)
+            })
+        }
+        Command::Gcd => {
+            let right = stack.pop().ok_or(RuntimeError::StackEmpty)?;
+            let left = stack.pop().ok_or(RuntimeError::StackEmpty)?;
+            stack.push(gcd(left, right).ok_or(RuntimeError::IntegerOverflow)?)
+        }
+        Command::Clamp => {
+            let high = stack.pop().ok_or(RuntimeError::StackEmpty)?;
+            let low = stack.pop().ok_or(RuntimeError::StackEmpty)?;
+            let value = stack.pop().ok_or(RuntimeError::StackEmpty)?;
+            if low > high {
+                return Err(RuntimeError::ClampBoundsInverted.into());
+            }
+            stack.push(value.clamp(low, high))
+        }
+    }
+    if stack.len() > options.max_stack {
+        return Err(RuntimeError::StackOverflow.into());
+    }
+    if state.mode > 2 {
+        return Err(RuntimeError::ModeOutOfRange.into());
+    }
+
+    Ok(None)
 }
 
-#[derive(Error, Debug)]
-pub enum ParseError {
-    #[error("Found character that is not a valid FXYT command at position `{0}`")]
-    InvalidCharacter(usize),
-    #[error("Found a bracket with no partner at position `{0}`")]
-    BracketMismatch(usize),
-    #[error("Attempt to enter a loop more than 8 levels deep")]
-    LoopNesting,
-}
+/// Also records where each command starts in `positions`, with loop bodies before the loop
+/// itself, as [`Program`] keeps them.
+///
+/// A digit appends to whatever is on top of the stack, so on an empty stack it could only
+/// fail. Wherever the stack is sure to be empty, like at the start of the program, straight
+/// after a `Z`, or once everything pushed so far has been popped again as in `XP`, a run of
+/// digits gets an implicit `N` in front of it instead, so `255` pushes 255 just like `N255`.
+/// Anywhere else digits append as usual, even if the value they'd append to might not be
+/// there.
+///
+/// `$` followed by hex digits pushes that value, so `$FF` is the same as `N255`. The digits
+/// have to be uppercase, unlike commands. It takes every hex digit that follows, so a command
+/// like `D` straight after needs a space first.
+///
+/// `:` and `;` store to and load from the register named by the digit straight after them,
+/// from `0` to `3`.
+fn parse(
+    program: &[u8],
+    offset: usize,
+    nesting: u8,
+    positions: &mut Vec<usize>,
+) -> Result<(usize, Vec<Command>), ParseError> {
+    let mut parsed = Vec::with_capacity(program.len());
+    let mut unparsed = program.iter().skip(offset);
+
+    let mut index = offset;
+    // how many values are on the stack, if that's known
+    let mut depth: Option<usize> = (nesting == 0).then_some(0);
+    while let Some(&c) = unparsed.next() {
+        let start = index;
+        if !c.is_ascii() {
+            return Err(ParseError::InvalidCharacter(char_span(program, index)));
+        }
+
+        let c = c.to_ascii_uppercase();
+        let next_command = match c {
+            c if c.is_ascii_whitespace() => {
+                index += 1;
+                continue;
+            }
+            b'#' => {
+                // comments run up to the end of the line, and can contain anything
+                let comment = program[index..].iter().take_while(|&&c| c != b'\n').count();
+                index += comment;
+                if comment > 1 {
+                    unparsed.nth(comment - 2);
+                }
+                continue;
+            }
+            b'X' | b'Y' | b'T' => Command::Coordinates(match c {
+                b'X' => Coordinates::X,
+                b'Y' => Coordinates::Y,
+                b'T' => Coordinates::T,
+                _ => unreachable!(),
+            }),
+            b'N' => Command::Integer,
+            d if d.is_ascii_digit() => {
+                if depth == Some(0) {
+                    parsed.push(Command::Integer);
+                    positions.push(start);
+                    depth = Some(1);
+                }
+                Command::Digit(d - b'0')
+            }
+            b'+' | b'-' | b'*' | b'/' | b'%' | b'E' => Command::Arithmetic(match c {
+                b'+' => Arithmetic::Plus,
+                b'-' => Arithmetic::Minus,
+                b'*' => Arithmetic::Times,
+                b'/' => Arithmetic::Divide,
+                b'%' => Arithmetic::Modulus,
+                b'E' => Arithmetic::Power,
+                _ => unreachable!(),
+            }),
+            b'$' => {
+                let hex_digits = program[index + 1..]
+                    .iter()
+                    .take_while(|c| matches!(c, b'0'..=b'9' | b'A'..=b'F'))
+                    .count();
+                if hex_digits == 0 {
+                    return Err(ParseError::InvalidCharacter(prefix_span(program, index)));
+                }
+                let value = program[index + 1..][..hex_digits]
+                    .iter()
+                    .try_fold(0isize, |value, &digit| {
+                        let digit = (digit as char).to_digit(16)? as isize;
+                        value.checked_mul(16)?.checked_add(digit)
+                    })
+                    .ok_or(ParseError::LiteralTooLarge(index))?;
+                index += hex_digits;
+                unparsed.nth(hex_digits - 1);
+
+                // the same as an N followed by the value in decimal
+                parsed.push(Command::Integer);
+                positions.push(start);
+                depth = depth.map(|depth| depth + 1);
+                let mut decimal = format!("{value}").into_bytes();
+                let last = decimal.pop().unwrap_or(b'0');
+                for digit in decimal {
+                    parsed.push(Command::Digit(digit - b'0'));
+                    positions.push(start);
+                }
+                Command::Digit(last - b'0')
+            }
+            b'M' => Command::Mode,
+            b'<' | b'>' if program.get(index + 1) == Some(&b'=') => {
+                index += 1;
+                unparsed.next();
+                Command::Comparison(if c == b'<' {
+                    Comparison::AtMost
+                } else {
+                    Comparison::AtLeast
+                })
+            }
+            b'=' | b'<' | b'>' => Command::Comparison(match c {
+                b'=' => Comparison::Equals,
+                b'<' => Comparison::LessThan,
+                b'>' => Comparison::GreaterThan,
+                _ => unreachable!(),
+            }),
+            b'!' => Command::Invert,
+            b'\'' => {
+                // a variant of whatever command comes next
+                let command = match program.get(index + 1).map(u8::to_ascii_uppercase) {
+                    Some(b')') => Command::Bitwise(Bitwise::ShiftRightLogical),
+                    Some(b'R') => Command::StackOperation(StackOperation::RotateBack),
+                    Some(b'D') => Command::StackOperation(StackOperation::DuplicatePair),
+                    Some(b'T') => Command::ScaledTime,
+                    _ => return Err(ParseError::InvalidCharacter(prefix_span(program, index))),
+                };
+                index += 1;
+                unparsed.next();
+                command
+            }
+            b'^' | b'&' | b'|' | b'(' | b')' => Command::Bitwise(match c {
+                b'^' => Bitwise::Xor,
+                b'&' => Bitwise::And,
+                b'|' => Bitwise::Or,
+                b'(' => Bitwise::ShiftLeft,
+                b')' => Bitwise::ShiftRight,
+                _ => unreachable!(),
+            }),
+            b'C' => Command::Clip,
+            b'"' => Command::SmoothStep,
+            b'\\' => Command::EaseInOut,
+            b'D' | b'P' | b'S' | b'R' | b'O' | b'J' | b'`' => Command::StackOperation(match c {
+                b'D' => StackOperation::Duplicate,
+                b'P' => StackOperation::Pop,
+                b'S' => StackOperation::Swap,
+                b'R' => StackOperation::Rotate,
+                b'O' => StackOperation::Over,
+                b'J' => StackOperation::SwapDeep,
+                b'`' => StackOperation::Roll,
+                _ => unreachable!(),
+            }),
+            b'[' => {
+                if nesting >= MAX_NESTING {
+                    return Err(ParseError::LoopNesting);
+                } else {
+                    let (eaten, loop_body) = parse(program, index + 1, nesting + 1, positions)?;
+                    index += eaten;
+                    unparsed.nth(eaten - 1);
+
+                    Command::Loop(loop_body)
+                }
+            }
+            b'?' | b'L' if program.get(index + 1) == Some(&b'[') => {
+                if nesting >= MAX_NESTING {
+                    return Err(ParseError::LoopNesting);
+                } else {
+                    let (eaten, body) = parse(program, index + 2, nesting + 1, positions)?;
+                    index += eaten + 1;
+                    unparsed.nth(eaten);
+
+                    if c == b'?' {
+                        Command::Conditional(body)
+                    } else {
+                        Command::While(body)
+                    }
+                }
+            }
+            b']' if nesting > 0 => return Ok((index - offset + 1, parsed)),
+            b'B' => Command::BitReverse,
+            b'U' => Command::DigitalRoot,
+            b'V' => Command::Spiral,
+            b'_' => Command::Accumulate,
+            b'F' => Command::FrameInterval,
+            b'W' => Command::Debug,
+            b'A' => Command::Absolute,
+            b'~' => Command::Negate,
+            b'Q' => Command::SquareRoot,
+            b'Z' => Command::Clear,
+            b'@' => Command::Depth,
+            b'{' => Command::Min,
+            b'}' => Command::Max,
+            b'H' => Command::Hash,
+            b'G' => Command::Gcd,
+            b'K' => Command::Clamp,
+            b'I' => Command::ModPow,
+            b',' => Command::Width,
+            b'.' => Command::Height,
+            b':' | b';' => {
+                let register = program
+                    .get(index + 1)
+                    .filter(|r| (b'0'..b'0' + REGISTER_COUNT as u8).contains(r))
+                    .ok_or_else(|| ParseError::InvalidCharacter(prefix_span(program, index)))?
+                    - b'0';
+                index += 1;
+                unparsed.next();
+                if c == b':' {
+                    Command::Store(register)
+                } else {
+                    Command::Load(register)
+                }
+            }
+
+            _ => return Err(ParseError::InvalidCharacter(index..index + 1)),
+        };
+
+        index += 1;
+
+        depth = match next_command {
+            Command::Clear => Some(0),
+            _ => depth.and_then(|depth| {
+                let (pops, pushes) = stack_effect(&next_command)?;
+                Some(depth.checked_sub(pops)? + pushes)
+            }),
+        };
+        parsed.push(next_command);
+        positions.push(start);
+    }
+
+    if nesting > 0 {
+        // offset is just past the opening bracket of this loop body
+        return Err(ParseError::BracketMismatch(offset - 1));
+    }
+
+    Ok((index - offset, parsed))
+}
+
+/// Where the character starting at `index` is, taking in the whole of it if it's more than one
+/// byte of UTF-8.
+fn char_span(program: &[u8], index: usize) -> Range<usize> {
+    let continuation_bytes = program[index + 1..]
+        .iter()
+        .take_while(|&&byte| byte & 0xC0 == 0x80)
+        .count();
+    index..index + 1 + continuation_bytes
+}
+
+/// Where a command at `index` that needs something particular after it is, along with the
+/// character that's there instead, if there is one.
+fn prefix_span(program: &[u8], index: usize) -> Range<usize> {
+    if index + 1 < program.len() {
+        index..char_span(program, index + 1).end
+    } else {
+        index..index + 1
+    }
+}
+
+/// The constant folding behind [`Program::optimize`]. `depth` is the number of values on the
+/// stack when `commands` starts, if that's known. Folding two pushes into one lowers the
+/// stack's peak, so that is only done where the original is known not to overflow.
+///
+/// The commands' `positions` are read in the order [`Program`] keeps them, and the positions
+/// of the folded commands are written to `folded_positions` in the same order.
+fn fold_constants(
+    commands: &[Command],
+    mut depth: Option<usize>,
+    max_stack: usize,
+    positions: &mut core::slice::Iter<usize>,
+    folded_positions: &mut Vec<usize>,
+) -> Vec<Command> {
+    let mut folded: Vec<Command> = Vec::with_capacity(commands.len());
+    for command in commands {
+        let command = match command {
+            Command::Integer => Command::Literal(0),
+            Command::Loop(inner_commands) => {
+                // every pass starts at the same depth only if the body leaves it as it was
+                let inner_depth = depth
+                    .and_then(|depth| depth.checked_sub(1))
+                    .filter(|_| net_stack_effect(inner_commands) == Some(0));
+                Command::Loop(fold_constants(
+                    inner_commands,
+                    inner_depth,
+                    max_stack,
+                    positions,
+                    folded_positions,
+                ))
+            }
+            Command::While(inner_commands) => {
+                // the body has to push the next condition, which is popped before it runs again
+                let inner_depth = depth
+                    .and_then(|depth| depth.checked_sub(1))
+                    .filter(|_| net_stack_effect(inner_commands) == Some(1));
+                Command::While(fold_constants(
+                    inner_commands,
+                    inner_depth,
+                    max_stack,
+                    positions,
+                    folded_positions,
+                ))
+            }
+            Command::Conditional(inner_commands) => {
+                let inner_depth = depth
+                    .and_then(|depth| depth.checked_sub(1))
+                    .filter(|_| net_stack_effect(inner_commands) == Some(0));
+                Command::Conditional(fold_constants(
+                    inner_commands,
+                    inner_depth,
+                    max_stack,
+                    positions,
+                    folded_positions,
+                ))
+            }
+            command => command.clone(),
+        };
+        let position = positions.next().copied();
+
+        let folded_value = match (folded.as_slice(), &command) {
+            (_, Command::Depth) => depth.map(|depth| (0, depth as isize)),
+            ([.., Command::Literal(top)], Command::Digit(d)) => top
+                .checked_mul(10)
+                .and_then(|top| top.checked_add(*d as isize))
+                .map(|value| (1, value)),
+            ([.., Command::Literal(top)], Command::Absolute) => {
+                top.checked_abs().map(|value| (1, value))
+            }
+            ([.., Command::Literal(top)], Command::Negate) => {
+                top.checked_neg().map(|value| (1, value))
+            }
+            ([.., Command::Literal(top)], Command::SquareRoot) if *top >= 0 => {
+                Some((1, top.isqrt()))
+            }
+            (&[.., Command::Literal(left), Command::Literal(right)], command)
+                if depth.is_some() =>
+            {
+                match command {
+                    Command::Arithmetic(a) => a.apply(left, right),
+                    Command::Comparison(c) => Some(c.apply(left, right)),
+                    Command::Bitwise(b) => Some(b.apply(left, right)),
+                    Command::Min => Some(left.min(right)),
+                    Command::Max => Some(left.max(right)),
+                    Command::Gcd => gcd(left, right),
+                    _ => None,
+                }
+                .map(|value| (2, value))
+            }
+            _ => None,
+        };
+
+        depth = match command {
+            Command::Clear => Some(0),
+            _ => depth.and_then(|depth| {
+                let (pops, pushes) = stack_effect(&command)?;
+                Some(depth.checked_sub(pops)? + pushes).filter(|&depth| depth <= max_stack)
+            }),
+        };
+
+        match folded_value {
+            Some((operands, value)) => {
+                folded.truncate(folded.len() - operands);
+                folded.push(Command::Literal(value));
+                if let Some(position) = position {
+                    // operands are always single commands, so their positions are the last ones
+                    let first = folded_positions.len() - operands;
+                    let position = folded_positions.get(first).copied().unwrap_or(position);
+                    folded_positions.truncate(first);
+                    folded_positions.push(position);
+                }
+            }
+            None => {
+                folded.push(command);
+                folded_positions.extend(position);
+            }
+        }
+    }
+
+    folded
+}
+
+/// How many values a command pops, then how many it pushes, when it runs without an error.
+/// `None` for `Z`, and for loops that don't leave the stack the same size they found it.
+fn stack_effect(command: &Command) -> Option<(usize, usize)> {
+    Some(match command {
+        Command::Coordinates(_)
+        | Command::Integer
+        | Command::Literal(_)
+        | Command::Depth
+        | Command::Load(_)
+        | Command::ScaledTime
+        | Command::Width
+        | Command::Height => (0, 1),
+        Command::Mode | Command::Debug => (0, 0),
+        Command::Digit(_)
+        | Command::Invert
+        | Command::Clip
+        | Command::SmoothStep
+        | Command::EaseInOut
+        | Command::DigitalRoot
+        | Command::Spiral
+        | Command::Hash
+        | Command::Absolute
+        | Command::Negate
+        | Command::SquareRoot => (1, 1),
+        Command::Arithmetic(_)
+        | Command::Comparison(_)
+        | Command::Bitwise(_)
+        | Command::BitReverse
+        | Command::Accumulate
+        | Command::Min
+        | Command::Max
+        | Command::Gcd => (2, 1),
+        Command::FrameInterval | Command::Store(_) => (1, 0),
+        Command::Clamp | Command::ModPow => (3, 1),
+        Command::StackOperation(so) => match so {
+            StackOperation::Duplicate => (1, 2),
+            StackOperation::DuplicatePair => (2, 4),
+            StackOperation::Pop => (1, 0),
+            StackOperation::Swap => (2, 2),
+            StackOperation::Rotate | StackOperation::RotateBack => (3, 3),
+            StackOperation::Over => (2, 3),
+            // at least the depth and the value on top, which stays where it was if it's 0
+            StackOperation::SwapDeep | StackOperation::Roll => (2, 1),
+        },
+        Command::Loop(inner_commands) | Command::Conditional(inner_commands)
+            if net_stack_effect(inner_commands) == Some(0) =>
+        {
+            (1, 0)
+        }
+        Command::While(inner_commands) if net_stack_effect(inner_commands) == Some(1) => (1, 0),
+        Command::Loop(_) | Command::Conditional(_) | Command::While(_) | Command::Clear => {
+            return None
+        }
+    })
+}
+
+fn net_stack_effect(commands: &[Command]) -> Option<isize> {
+    commands.iter().try_fold(0, |net, command| {
+        let (pops, pushes) = stack_effect(command)?;
+        Some(net + pushes as isize - pops as isize)
+    })
+}
+
+/// Runs the program over whether each stack value depends on `T`, rather than the values
+/// themselves, for [`Program::is_animated`]. Gives up with `None` if `T` could change more than
+/// the values, such as whether an error happens or how many times a loop runs.
+fn trace_t(commands: &[Command], stack: &mut Vec<bool>) -> Option<()> {
+    // running off the bottom means either an error, which happens in every frame, or values
+    // left below a loop that don't depend on T
+    fn pop(stack: &mut Vec<bool>) -> bool {
+        stack.pop().unwrap_or(false)
+    }
+
+    for command in commands {
+        match command {
+            Command::Coordinates(c) => stack.push(*c == Coordinates::T),
+            Command::StackOperation(StackOperation::Duplicate) => {
+                let top = pop(stack);
+                stack.extend_from_slice(&[top, top]);
+            }
+            Command::StackOperation(StackOperation::DuplicatePair) => {
+                let top = pop(stack);
+                let second = pop(stack);
+                stack.extend_from_slice(&[second, top, second, top]);
+            }
+            Command::StackOperation(StackOperation::Swap) => {
+                let right = pop(stack);
+                let left = pop(stack);
+                stack.extend_from_slice(&[right, left]);
+            }
+            Command::StackOperation(StackOperation::Rotate) => {
+                let top = pop(stack);
+                let second = pop(stack);
+                let third = pop(stack);
+                stack.extend_from_slice(&[second, top, third]);
+            }
+            Command::StackOperation(StackOperation::RotateBack) => {
+                let top = pop(stack);
+                let second = pop(stack);
+                let third = pop(stack);
+                stack.extend_from_slice(&[top, third, second]);
+            }
+            Command::StackOperation(StackOperation::Over) => {
+                let top = pop(stack);
+                let second = pop(stack);
+                stack.extend_from_slice(&[second, top, second]);
+            }
+            Command::StackOperation(StackOperation::SwapDeep | StackOperation::Roll) => {
+                // the depth isn't followed, so there's no telling where a value from T ends up
+                if pop(stack) || stack.contains(&true) {
+                    return None;
+                }
+            }
+            Command::While(inner_commands) => {
+                if pop(stack) {
+                    return None;
+                }
+                if net_stack_effect(inner_commands) == Some(1) {
+                    // same as for loops below, with the condition popped after every pass
+                    loop {
+                        let before = stack.clone();
+                        trace_t(inner_commands, stack)?;
+                        if pop(stack) {
+                            return None;
+                        }
+                        for (after, before) in stack.iter_mut().rev().zip(before.iter().rev()) {
+                            *after |= before;
+                        }
+                        if *stack == before {
+                            break;
+                        }
+                    }
+                } else if stack.contains(&true) || uses_t(inner_commands) {
+                    return None;
+                } else {
+                    stack.clear();
+                }
+            }
+            Command::Clear => stack.clear(),
+            Command::Store(_) => {
+                // registers aren't followed, so anything from T could come back out of one
+                if pop(stack) {
+                    return None;
+                }
+            }
+            Command::ScaledTime => stack.push(true),
+            Command::Hash => {
+                // mixes in T whatever the seed was
+                pop(stack);
+                stack.push(true);
+            }
+            Command::Accumulate => {
+                // the value underneath keeps whatever it depended on before
+                if pop(stack) {
+                    return None;
+                }
+            }
+            // a conditional body runs at most once, which the loop handling covers too
+            Command::Loop(inner_commands) | Command::Conditional(inner_commands) => {
+                if pop(stack) {
+                    return None;
+                }
+                if net_stack_effect(inner_commands) == Some(0) {
+                    // the body can run any number of times, so keep going until another pass
+                    // doesn't make anything new depend on T. Values line up from the top.
+                    loop {
+                        let before = stack.clone();
+                        trace_t(inner_commands, stack)?;
+                        for (after, before) in stack.iter_mut().rev().zip(before.iter().rev()) {
+                            *after |= before;
+                        }
+                        if *stack == before {
+                            break;
+                        }
+                    }
+                } else if stack.contains(&true) || uses_t(inner_commands) {
+                    return None;
+                } else {
+                    // nothing depends on T, however deep the stack ends up
+                    stack.clear();
+                }
+            }
+            command => {
+                let (pops, pushes) = stack_effect(command)?;
+                let from_t = (0..pops).fold(false, |from_t, _| pop(stack) | from_t);
+                let fallible = matches!(
+                    command,
+                    Command::Digit(_)
+                        | Command::Arithmetic(_)
+                        | Command::BitReverse
+                        | Command::FrameInterval
+                        | Command::Absolute
+                        | Command::Negate
+                        | Command::SquareRoot
+                        | Command::Gcd
+                        | Command::Clamp
+                        | Command::ModPow
+                );
+                if from_t && fallible {
+                    return None;
+                }
+                stack.extend((0..pushes).map(|_| from_t));
+            }
+        }
+    }
+
+    Some(())
+}
+
+/// Warns about whatever comes after a command that always stops the program. `first` is
+/// where `commands` starts among all the program's commands, counted as in `positions`.
+fn find_unreachable(
+    commands: &[Command],
+    first: usize,
+    positions: &[usize],
+    warnings: &mut Vec<Warning>,
+) {
+    let mut index = first;
+    // whether the value on top is sure to be 0, like after `N` or `N00`
+    let mut zero_on_top = false;
+    for (offset, command) in commands.iter().enumerate() {
+        if let Command::Loop(inner_commands)
+        | Command::Conditional(inner_commands)
+        | Command::While(inner_commands) = command
+        {
+            find_unreachable(inner_commands, index, positions, warnings);
+        }
+        index += command_count(command);
+
+        // dividing by zero stops the program in every mode, with an error or a colour
+        let stops = match command {
+            Command::Debug => true,
+            Command::Arithmetic(Arithmetic::Divide | Arithmetic::Modulus) => zero_on_top,
+            _ => false,
+        };
+        zero_on_top = match command {
+            Command::Integer | Command::Literal(0) => true,
+            Command::Digit(0) => zero_on_top,
+            _ => false,
+        };
+
+        if let (true, Some(next)) = (stops, commands.get(offset + 1)) {
+            // a loop comes after its body, and is recorded where it starts
+            let next_index = index + command_count(next) - 1;
+            warnings.push(Warning::Unreachable {
+                pos: positions.get(next_index).copied(),
+            });
+            return;
+        }
+    }
+}
+
+fn uses_t(commands: &[Command]) -> bool {
+    commands.iter().any(|command| match command {
+        Command::Coordinates(Coordinates::T) | Command::ScaledTime | Command::Hash => true,
+        Command::Loop(inner_commands)
+        | Command::Conditional(inner_commands)
+        | Command::While(inner_commands) => uses_t(inner_commands),
+        _ => false,
+    })
+}
+
+/// A parsed FXYT program, ready to be rendered. With the `serde` feature it serializes as its
+/// list of commands. Two programs are equal if they have the same commands, however they were
+/// spaced out in the source.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct Program {
+    commands: Vec<Command>,
+    /// Where each command starts in the source, loop bodies first and then the loop, for
+    /// reporting errors. Empty if there's no source, as for a deserialized program.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    positions: Vec<usize>,
+}
+
+impl PartialEq for Program {
+    fn eq(&self, other: &Self) -> bool {
+        self.commands == other.commands
+    }
+}
+
+impl Eq for Program {}
+
+/// Settings that change how a program is rendered. The default matches [`render`].
+#[derive(Clone)]
+pub struct RenderOptions {
+    /// How many pixels wide each frame is. 256 by default. `X` runs over the whole width, so a
+    /// wider frame shows more of the pattern rather than a scaled up one.
+    pub width: usize,
+    /// How many pixels high each frame is. 256 by default.
+    pub height: usize,
+    /// Play `T` forwards over the even values and back down over the odd ones instead of
+    /// counting straight from 0 to 255, so the last frame flows back into the first when the
+    /// animation loops.
+    pub seamless: bool,
+    /// How many frames to render for [animated](Program::is_animated) programs, with `T`
+    /// running from `t_start` to `t_start + frames - 1`. Defaults to 256. Other programs are
+    /// always a single frame. Each frame still gets its own interval from `F`, so this changes
+    /// the length of the animation rather than its speed.
+    pub frames: Option<usize>,
+    /// The value of `T` in the first frame. Later frames wrap around to 0 past `usize::MAX`.
+    pub t_start: usize,
+    /// How the values left on the stack are turned into a colour.
+    pub colours: ColourMode,
+    /// Gamma encode every colour channel (but not alpha) with this exponent, e.g. 2.2 for
+    /// roughly sRGB, so that linear ramps look even on screen. Ignored unless positive.
+    /// Off by default.
+    #[cfg(feature = "std")]
+    pub gamma: Option<f32>,
+    /// The most commands a single pixel may run, counting each pass through a loop body
+    /// separately and each pass itself as one more, before rendering fails with
+    /// [`RuntimeError::OpBudgetExceeded`]. Useful for rendering programs you don't trust.
+    /// Unlimited by default.
+    pub max_ops: Option<u64>,
+    /// Whether `W` stops rendering with [`RuntimeError::DebugHalt`] once it's printed the
+    /// stack. On by default; turn it off to peek at the stack and carry on.
+    pub debug_halt: bool,
+    /// Where `W` prints the coordinates and the stack, instead of standard error. It's behind
+    /// a lock since pixels can be rendered on several threads at once.
+    #[cfg(feature = "std")]
+    pub debug_output: Option<Arc<Mutex<dyn Write + Send>>>,
+    /// Put `Y = 0` in the top row of each frame, as most image formats count rows, instead
+    /// of at the bottom. Off by default, so `Y` points up.
+    pub y_down: bool,
+    /// How many values the stack can hold before a push fails with
+    /// [`RuntimeError::StackOverflow`]. 8 by default, as in the original FXYT. Registers
+    /// don't count towards it.
+    pub max_stack: usize,
+    /// Antialias by running the program on a grid `samples` times finer in each direction
+    /// and averaging each `samples` by `samples` block down to one pixel. The program sees
+    /// coordinates up to `256 * samples`, so it has to scale anything it compares them to.
+    /// 1, the default, and 0 both render every pixel once.
+    pub samples: u8,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions {
+            width: 256,
+            height: 256,
+            seamless: false,
+            frames: None,
+            t_start: 0,
+            colours: ColourMode::default(),
+            #[cfg(feature = "std")]
+            gamma: None,
+            max_ops: None,
+            debug_halt: true,
+            #[cfg(feature = "std")]
+            debug_output: None,
+            y_down: false,
+            max_stack: 8,
+            samples: 1,
+        }
+    }
+}
+
+/// Builder methods, so options can be set in a chain like
+/// `RenderOptions::new().size(512, 512).frames(64)` without spelling out the rest.
+impl RenderOptions {
+    /// The defaults, the same as [`RenderOptions::default`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets [`width`](Self::width) and [`height`](Self::height).
+    pub fn size(self, width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            ..self
+        }
+    }
+
+    /// Sets [`seamless`](Self::seamless).
+    pub fn seamless(self, seamless: bool) -> Self {
+        Self { seamless, ..self }
+    }
+
+    /// Sets [`frames`](Self::frames).
+    pub fn frames(self, frames: usize) -> Self {
+        Self {
+            frames: Some(frames),
+            ..self
+        }
+    }
+
+    /// Sets [`t_start`](Self::t_start).
+    pub fn t_start(self, t_start: usize) -> Self {
+        Self { t_start, ..self }
+    }
+
+    /// Sets [`colours`](Self::colours).
+    pub fn colours(self, colours: ColourMode) -> Self {
+        Self { colours, ..self }
+    }
+
+    /// Sets [`gamma`](Self::gamma).
+    #[cfg(feature = "std")]
+    pub fn gamma(self, gamma: f32) -> Self {
+        Self {
+            gamma: Some(gamma),
+            ..self
+        }
+    }
+
+    /// Sets [`max_ops`](Self::max_ops).
+    pub fn max_ops(self, max_ops: u64) -> Self {
+        Self {
+            max_ops: Some(max_ops),
+            ..self
+        }
+    }
+
+    /// Sets [`debug_halt`](Self::debug_halt).
+    pub fn debug_halt(self, debug_halt: bool) -> Self {
+        Self { debug_halt, ..self }
+    }
+
+    /// Sets [`debug_output`](Self::debug_output).
+    #[cfg(feature = "std")]
+    pub fn debug_output(self, debug_output: Arc<Mutex<dyn Write + Send>>) -> Self {
+        Self {
+            debug_output: Some(debug_output),
+            ..self
+        }
+    }
+
+    /// Sets [`y_down`](Self::y_down).
+    pub fn y_down(self, y_down: bool) -> Self {
+        Self { y_down, ..self }
+    }
+
+    /// Sets [`max_stack`](Self::max_stack).
+    pub fn max_stack(self, max_stack: usize) -> Self {
+        Self { max_stack, ..self }
+    }
+
+    /// Sets [`samples`](Self::samples).
+    pub fn samples(self, samples: u8) -> Self {
+        Self { samples, ..self }
+    }
+}
+
+impl core::fmt::Debug for RenderOptions {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let mut debug = f.debug_struct("RenderOptions");
+        debug
+            .field("width", &self.width)
+            .field("height", &self.height)
+            .field("seamless", &self.seamless)
+            .field("frames", &self.frames)
+            .field("t_start", &self.t_start)
+            .field("colours", &self.colours);
+        #[cfg(feature = "std")]
+        debug.field("gamma", &self.gamma);
+        debug
+            .field("max_ops", &self.max_ops)
+            .field("debug_halt", &self.debug_halt);
+        // writers can't be printed, so just say whether there is one
+        #[cfg(feature = "std")]
+        debug.field("debug_output", &self.debug_output.as_ref().map(|_| ".."));
+        debug
+            .field("y_down", &self.y_down)
+            .field("max_stack", &self.max_stack)
+            .field("samples", &self.samples)
+            .finish()
+    }
+}
+
+/// What [`validate`] found out about a program.
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct ProgramInfo {
+    /// See [`Program::is_animated`].
+    pub animated: bool,
+    /// How deeply loops, `?[...]` and `L[...]` are nested inside each other, 0 if there are
+    /// none.
+    pub max_nesting: usize,
+    /// How many commands there are, including the ones inside loops.
+    pub command_count: usize,
+    /// Whether there's a `W` anywhere.
+    pub has_debug: bool,
+    /// Whether there's an `F` anywhere.
+    pub has_frame_interval: bool,
+    /// Things that are probably mistakes, in the order they come in the source.
+    pub warnings: Vec<Warning>,
+}
+
+/// What ran during a render, from [`render_with_stats`].
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct Stats {
+    /// How many times each command ran, over every pixel of every frame, keyed by how it's
+    /// written, e.g. `"X"`, `"<="` or `":2"`. Loops are keyed by their opening bracket, `"["`,
+    /// `"?["` or `"L["`, and count once each time they're reached, with whatever is in their
+    /// body counted separately.
+    pub counts: BTreeMap<String, u64>,
+    /// The most values that were ever on the stack at once.
+    pub max_depth: usize,
+}
+
+impl Stats {
+    // kept out of line so that rendering without stats doesn't pay for it
+    #[cold]
+    #[inline(never)]
+    fn count(&mut self, command: &Command, depth: usize) {
+        *self.counts.entry(command.kind()).or_default() += 1;
+        // the depth each command starts at is the depth the one before it left, so only the
+        // last command's needs recording separately
+        self.max_depth = self.max_depth.max(depth);
+    }
+}
+
+/// Settings for [`write_gif_with`]. The default matches [`write_gif`].
+#[cfg(feature = "gif")]
+#[derive(Copy, Clone, Default, Debug)]
+pub struct GifOptions {
+    /// Quantize all the frames at once into a single global palette, instead of giving each
+    /// frame its own. Smaller files, and colours don't shift between frames, but animations
+    /// with a lot of different colours over time lose more detail.
+    pub global_palette: bool,
+    /// Nudge pixels up and down in a fixed 4x4 Bayer pattern before quantizing, which breaks
+    /// the bands a smooth gradient turns into up into a fine cross-hatch. Deterministic, so
+    /// the same frames always encode to the same file.
+    pub dither: bool,
+    /// Show frames that don't set their own [interval](Frame::interval) with `F` for this
+    /// many centiseconds, instead of the default 100.
+    pub delay: Option<u16>,
+}
+
+#[cfg(feature = "gif")]
+impl GifOptions {
+    /// Options that play the animation at `fps` frames per second, apart from frames that set
+    /// their own interval with `F`. GIF delays are whole centiseconds, so this is rounded to
+    /// the nearest one: 25fps is exactly 4cs, but 30fps becomes 3cs, really about 33fps.
+    /// Anything over 200fps is played at 100fps, the fastest a GIF can go, and 0fps leaves
+    /// the default interval alone.
+    pub fn from_fps(fps: u32) -> Self {
+        GifOptions {
+            delay: (fps > 0).then(|| ((100 + fps / 2) / fps).max(1) as u16),
+            ..Default::default()
+        }
+    }
+}
+
+/// A lookup from colours to their index in a GIF's palette.
+#[cfg(feature = "gif")]
+type IndexOf = Box<dyn Fn(&RGB8) -> u8>;
+
+#[cfg(feature = "gif")]
+fn gif_error(error: gif::EncodingError) -> io::Error {
+    match error {
+        gif::EncodingError::Io(error) => error,
+        error => io::Error::new(io::ErrorKind::InvalidInput, error),
+    }
+}
+
+/// Applies an ordered dither, so bands get broken up before they're quantized.
+#[cfg(feature = "gif")]
+fn dither_frame(frame: &Frame) -> Frame {
+    const BAYER: [[i16; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+    let image = frame
+        .image
+        .iter()
+        .enumerate()
+        .map(|(y, row)| {
+            row.iter()
+                .enumerate()
+                .map(|(x, pixel)| {
+                    // -15 to 15, centred so the average brightness stays the same
+                    let offset = BAYER[y % 4][x % 4] * 2 - 15;
+                    let channel = |value: u8| (value as i16 + offset).clamp(0, 255) as u8;
+                    RGB8::new(channel(pixel.r), channel(pixel.g), channel(pixel.b))
+                })
+                .collect()
+        })
+        .collect();
+    Frame {
+        interval: frame.interval,
+        custom_interval: frame.custom_interval,
+        image,
+    }
+}
+
+/// Encodes a looping GIF a frame at a time, like [`write_gif_with`] does, so that frames from
+/// [`Program::render_frames_with`] don't all have to be held in memory. A
+/// [global palette](GifOptions::global_palette) needs every frame up front, so that option is
+/// ignored here and each frame gets its own.
+#[cfg(feature = "gif")]
+pub struct GifWriter<W: Write> {
+    encoder: gif::Encoder<W>,
+    width: u16,
+    height: u16,
+    options: GifOptions,
+    index_of: Option<IndexOf>,
+}
+
+#[cfg(feature = "gif")]
+impl<W: Write> GifWriter<W> {
+    /// Starts a GIF whose frames are `width` by `height` pixels, which can't be more than
+    /// 65535.
+    pub fn new(out: W, width: usize, height: usize, options: &GifOptions) -> io::Result<Self> {
+        Self::with_palette(out, width, height, options, None)
+    }
+
+    fn with_palette(
+        out: W,
+        width: usize,
+        height: usize,
+        options: &GifOptions,
+        global: Option<(Vec<u8>, IndexOf)>,
+    ) -> io::Result<Self> {
+        let (Ok(width), Ok(height)) = (u16::try_from(width), u16::try_from(height)) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "GIFs can't be more than 65535 pixels wide or tall",
+            ));
+        };
+        let (palette, index_of) = match global {
+            Some((palette, index_of)) => (palette, Some(index_of)),
+            None => (Vec::new(), None),
+        };
+
+        let mut encoder = gif::Encoder::new(out, width, height, &palette).map_err(gif_error)?;
+        encoder
+            .set_repeat(gif::Repeat::Infinite)
+            .map_err(gif_error)?;
+        Ok(Self {
+            encoder,
+            width,
+            height,
+            options: *options,
+            index_of,
+        })
+    }
+
+    /// Adds the next frame, shown for its own interval unless [`GifOptions::delay`] says
+    /// otherwise.
+    pub fn write_frame(&mut self, frame: &Frame) -> io::Result<()> {
+        let dithered;
+        let frame = match self.options.dither {
+            true => {
+                dithered = dither_frame(frame);
+                &dithered
+            }
+            false => frame,
+        };
+
+        let (width, height) = (self.width, self.height);
+        let mut gif_frame = match &self.index_of {
+            Some(index_of) => gif::Frame {
+                width,
+                height,
+                buffer: frame.image.iter().flatten().map(index_of).collect(),
+                ..Default::default()
+            },
+            None => gif::Frame::from_rgb(width, height, &frame.rgb_bytes()),
+        };
+        // both are in centiseconds
+        gif_frame.delay = match self.options.delay {
+            Some(delay) if !frame.custom_interval => delay,
+            _ => frame.interval.min(u16::MAX as u32) as u16,
+        };
+        self.encoder.write_frame(&gif_frame).map_err(gif_error)
+    }
+
+    /// Finishes the GIF off, and hands back what it was written to.
+    pub fn finish(self) -> io::Result<W> {
+        self.encoder.into_inner().map_err(gif_error)
+    }
+}
+
+/// Encodes an animated PNG a frame at a time, like [`write_apng`] does, so that frames from
+/// [`Program::render_frames_with`] don't all have to be held in memory. Unlike a GIF, it
+/// needs to know how many frames there will be up front.
+#[cfg(feature = "apng")]
+pub struct ApngWriter<W: Write> {
+    writer: png::Writer<W>,
+}
+
+#[cfg(feature = "apng")]
+impl<W: Write> ApngWriter<W> {
+    /// Starts an animation of `frames` frames, each `width` by `height` pixels.
+    pub fn new(out: W, width: usize, height: usize, frames: usize) -> io::Result<Self> {
+        let mut encoder = png::Encoder::new(out, width as u32, height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_animated(frames as u32, 0)?;
+        Ok(Self {
+            writer: encoder.write_header()?,
+        })
+    }
+
+    /// Adds the next frame, shown for its own interval.
+    pub fn write_frame(&mut self, frame: &Frame) -> io::Result<()> {
+        // intervals are in centiseconds, fall back to whole seconds if that won't fit
+        let (numerator, denominator) = match u16::try_from(frame.interval) {
+            Ok(interval) => (interval, 100),
+            Err(_) => ((frame.interval / 100).min(u16::MAX as u32) as u16, 1),
+        };
+        self.writer.set_frame_delay(numerator, denominator)?;
+        self.writer.write_image_data(&frame.rgb_bytes())?;
+        Ok(())
+    }
+
+    /// Finishes the animation off, once every frame it was started with has been written.
+    pub fn finish(self) -> io::Result<()> {
+        self.writer.finish()?;
+        Ok(())
+    }
+}
+
+/// How the values left on the stack are turned into a colour. In every mode, each of the top
+/// three has to be between 0 and 255 inclusive.
+#[derive(Copy, Clone, Default, PartialEq, Eq, Debug)]
+pub enum ColourMode {
+    /// The top three values are red, green and blue, with blue on top.
+    #[default]
+    Rgb,
+    /// The top three values are hue, saturation and value, with value on top. Hue goes once
+    /// round the colour wheel from 0 to 255, starting and ending at red.
+    Hsv,
+    /// Like [`ColourMode::Rgb`], except that a lone value on the stack is used for all three
+    /// channels instead of just blue.
+    Grayscale,
+}
+
+/// One rendered frame. Pixels are [`RGB8`], except for frames from [`render_rgba8`], which
+/// are [`RGBA8`].
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Frame<P = RGB8> {
+    /// How long to show this frame for, in centiseconds. Set by the `F` command, otherwise 100.
+    pub interval: u32,
+    /// Whether [`interval`](Self::interval) was set by `F`, rather than left at the default.
+    pub custom_interval: bool,
+    /// Row-major pixels, top row first. Every row has the same length.
+    pub image: Vec<Vec<P>>,
+}
+
+impl<P> Frame<P> {
+    pub fn width(&self) -> usize {
+        self.image.first().map_or(0, Vec::len)
+    }
+
+    pub fn height(&self) -> usize {
+        self.image.len()
+    }
+
+    fn pack<const N: usize>(&self, bytes: impl Fn(&P) -> [u8; N]) -> FrameBytes {
+        FrameBytes {
+            width: self.width(),
+            height: self.height(),
+            interval: self.interval,
+            data: self.image.iter().flatten().flat_map(bytes).collect(),
+        }
+    }
+
+    fn encode(mut self, table: &[u8; 256]) -> Self
+    where
+        P: Colour,
+    {
+        for pixel in self.image.iter_mut().flatten() {
+            *pixel = pixel.map_channels(table);
+        }
+        self
+    }
+}
+
+impl Frame {
+    /// Packs the pixels into `RGBRGB...` bytes, top row first.
+    pub fn to_rgb_bytes(&self) -> FrameBytes {
+        self.pack(|pixel| [pixel.r, pixel.g, pixel.b])
+    }
+
+    /// Packs the pixels into `RGBARGBA...` bytes, top row first, with every alpha at 255.
+    pub fn to_rgba_bytes(&self) -> FrameBytes {
+        self.pack(|pixel| [pixel.r, pixel.g, pixel.b, 255])
+    }
+
+    #[cfg(any(feature = "png", feature = "gif"))]
+    fn rgb_bytes(&self) -> Vec<u8> {
+        self.to_rgb_bytes().data
+    }
+}
+
+impl Frame<RGBA8> {
+    /// Packs the pixels into `RGBARGBA...` bytes, top row first, keeping their alpha.
+    pub fn to_rgba_bytes(&self) -> FrameBytes {
+        self.pack(|pixel| [pixel.r, pixel.g, pixel.b, pixel.a])
+    }
+}
+
+/// A [`Frame`] with its pixels packed tightly into bytes, for handing straight to a texture
+/// or canvas. Rows run top to bottom, as in [`Frame::image`].
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct FrameBytes {
+    pub width: usize,
+    pub height: usize,
+    /// See [`Frame::interval`].
+    pub interval: u32,
+    pub data: Vec<u8>,
+}
+
+/// One command of a parsed program. Displaying it writes it back out as source, loop bodies
+/// and all.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Command {
+    Coordinates(Coordinates),
+    /// Written `N`. Pushes 0, for the digits after it to build on.
+    Integer,
+    /// Pushes a value worked out ahead of time by [`Program::optimize`]. Never produced by
+    /// the parser.
+    Literal(isize),
+    /// Appends a decimal digit to the value on top of the stack.
+    Digit(u8),
+    Arithmetic(Arithmetic),
+    /// Written `M`. Moves on to the next way of handling division by zero: an error, then
+    /// black, then red.
+    Mode,
+    Comparison(Comparison),
+    /// Written `!`. Pushes 1 for a 0, and 0 for anything else.
+    Invert,
+    Bitwise(Bitwise),
+    /// Pops a value and pushes it clamped between 0 and 255.
+    Clip,
+    /// Written `"`. Eases a value from 0 to 255 in and out with smoothstep, first clipping it
+    /// like [`Command::Clip`].
+    SmoothStep,
+    /// Written `\`. Like [`Command::SmoothStep`], but with a cubic curve, so it starts and
+    /// finishes slower, and moves faster through the middle.
+    EaseInOut,
+    StackOperation(StackOperation),
+    /// Pops a count and runs the body that many times. A count of 0 or less skips it. The
+    /// stack limit is checked after every command inside, on every pass.
+    Loop(Vec<Command>),
+    /// Written `?[...]`. Pops a value and runs the body once if it's nonzero. Counts towards
+    /// the same nesting limit as loops.
+    Conditional(Vec<Command>),
+    /// Written `L[...]`. Pops a value and runs the body if it's nonzero, then does the same
+    /// again with whatever the body left on top, until it pops a zero. Errors with
+    /// `IterationLimit` if the body would run more than 4096 times in a row.
+    While(Vec<Command>),
+    /// Pops a bit width, then a value, and pushes the value with its lowest `width` bits
+    /// reversed. Bits above the width are left alone.
+    BitReverse,
+    /// Pops a value and pushes the digital root of its absolute value, i.e. its base-10
+    /// digits summed repeatedly until a single digit is left.
+    DigitalRoot,
+    /// Pops a twist factor and pushes `angle + radius * twist` wrapped into `0..256`, where
+    /// the radius and angle are measured from (128, 128), the centre of a default sized canvas.
+    /// Larger twists wind the bands more tightly.
+    Spiral,
+    /// Pops the top value and adds it to the bottom of the stack (index 0). Errors with
+    /// `StackEmpty` if there's no value to pop, or nothing left underneath it to add to.
+    Accumulate,
+    /// Written `F`. Pops how long the frame is shown for, in hundredths of a second.
+    FrameInterval,
+    /// Written `W`. Prints the coordinates and the stack, then stops the program if
+    /// [`RenderOptions::debug_halt`] is on.
+    Debug,
+    /// Pops a value and pushes its absolute value. Errors with `IntegerOverflow` for
+    /// `isize::MIN`, which has no positive counterpart.
+    Absolute,
+    /// Pops a value and pushes it with the sign flipped. Errors with `IntegerOverflow` for
+    /// `isize::MIN`.
+    Negate,
+    /// Pops a value and pushes its square root, rounded down. Errors with `NegativeSqrt` for
+    /// negative values.
+    SquareRoot,
+    /// Throws away everything on the stack.
+    Clear,
+    /// Pushes how many values were on the stack before it, so `@` on an empty stack pushes 0.
+    Depth,
+    /// Pops two values and pushes the smaller one.
+    Min,
+    /// Pops two values and pushes the larger one.
+    Max,
+    /// Pops a seed and pushes a pseudo-random value in `0..256` from it and the coordinates.
+    Hash,
+    /// Pops a value into one of the registers, which don't count towards the stack limit.
+    /// There are 4, numbered from 0, and using any other fails with
+    /// [`RuntimeError::RegisterOutOfRange`].
+    Store(u8),
+    /// Pushes the value in a register, which is 0 until something is stored there.
+    Load(u8),
+    /// Pops two values and pushes their greatest common divisor, which is never negative.
+    Gcd,
+    /// Pops an upper bound, a lower bound and a value, and pushes the value clamped between
+    /// them. `C` is the same with the bounds 0 and 255.
+    Clamp,
+    /// Pops a modulus, an exponent and a base, and pushes the base to the power of the
+    /// exponent, modulo the size of the modulus. Never overflows, whatever the exponent.
+    ModPow,
+    /// Written `'T`. Pushes how far through the animation the frame is, from 0 in the first
+    /// frame up to just under 256 in the last, however many [`frames`](RenderOptions::frames)
+    /// there are.
+    ScaledTime,
+    /// Pushes how many pixels wide the canvas is.
+    Width,
+    /// Pushes how many pixels high the canvas is.
+    Height,
+}
+
+/// The commands that push where and when the pixel is.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Coordinates {
+    X,
+    Y,
+    T,
+}
+
+/// The commands that pop two values and push the result of doing sums with them.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Arithmetic {
+    Plus,
+    Minus,
+    Times,
+    Divide,
+    Modulus,
+    /// Pops an exponent, then a base. Errors with `NegativeExponent` for exponents below 0.
+    Power,
+}
+
+/// The commands that pop two values and push 1 if they compare the right way, or 0 if not.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Comparison {
+    Equals,
+    LessThan,
+    GreaterThan,
+    /// Written `<=`.
+    AtMost,
+    /// Written `>=`.
+    AtLeast,
+}
+
+/// The commands that pop two values and push the result of working on their bits.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Bitwise {
+    Xor,
+    And,
+    Or,
+    /// Pops a shift amount, then a value. Negative amounts count as 0, and amounts of
+    /// `isize::BITS` or more shift every bit out.
+    ShiftLeft,
+    /// Like [`Bitwise::ShiftLeft`], but arithmetic, so negative values stay negative and
+    /// shift towards -1 rather than 0.
+    ShiftRight,
+    /// Written `')`. Like [`Bitwise::ShiftRight`], but logical: the value's bits are shifted
+    /// as if it were unsigned, filling in with zeroes, so negative values turn positive.
+    ShiftRightLogical,
+}
+
+impl Arithmetic {
+    /// `None` on overflow, and for a zero divisor or negative exponent, which the caller has to
+    /// deal with itself since they aren't overflows.
+    fn apply(self, left: isize, right: isize) -> Option<isize> {
+        match self {
+            Arithmetic::Plus => left.checked_add(right),
+            Arithmetic::Minus => left.checked_sub(right),
+            Arithmetic::Times => left.checked_mul(right),
+            Arithmetic::Divide => left.checked_div(right),
+            Arithmetic::Modulus => left.checked_rem(right),
+            Arithmetic::Power if right < 0 => None,
+            Arithmetic::Power => {
+                // anything that big overflows unless the base is 0 or ±1, where only the
+                // exponent's parity matters
+                let exponent = u32::try_from(right).unwrap_or(u32::MAX - 1 + (right & 1) as u32);
+                left.checked_pow(exponent)
+            }
+        }
+    }
+}
+
+impl Comparison {
+    fn apply(self, left: isize, right: isize) -> isize {
+        let result = match self {
+            Comparison::Equals => left == right,
+            Comparison::LessThan => left < right,
+            Comparison::GreaterThan => left > right,
+            Comparison::AtMost => left <= right,
+            Comparison::AtLeast => left >= right,
+        };
+        result as isize
+    }
+}
+
+impl Bitwise {
+    fn apply(self, left: isize, right: isize) -> isize {
+        match self {
+            Bitwise::Xor => left ^ right,
+            Bitwise::And => left & right,
+            Bitwise::Or => left | right,
+            Bitwise::ShiftLeft | Bitwise::ShiftRight | Bitwise::ShiftRightLogical => {
+                let amount = right.clamp(0, isize::BITS as isize) as u32;
+                match self {
+                    Bitwise::ShiftLeft => left.checked_shl(amount).unwrap_or(0),
+                    Bitwise::ShiftRight => left >> amount.min(isize::BITS - 1),
+                    _ => (left as usize).checked_shr(amount).unwrap_or(0) as isize,
+                }
+            }
+        }
+    }
+}
+
+/// The commands that move values around the stack without changing them.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum StackOperation {
+    Duplicate,
+    /// Written `'D`. Copies the top two values as a pair, so `a b` becomes `a b a b`.
+    DuplicatePair,
+    Pop,
+    Swap,
+    /// Brings the third value from the top up to the top, so `a b c` becomes `b c a`, the
+    /// same as `rot` in Forth.
+    Rotate,
+    /// Written `'R`. Undoes [`StackOperation::Rotate`], so `a b c` becomes `c a b`.
+    RotateBack,
+    /// Pushes a copy of the second value from the top, so `a b` becomes `a b a`.
+    Over,
+    /// Pops a depth `n` and swaps the top value with the one `n` below it, so `1J` is the same
+    /// as `S` and `0J` leaves the stack as it is. Fails with [`RuntimeError::StackEmpty`] if
+    /// there's nothing that deep, or if `n` is negative.
+    SwapDeep,
+    /// Pops a depth `n` and brings the value `n` below the top up to the top, moving the ones
+    /// above it down, so `` 1` `` is the same as `S` and `` 2` `` the same as `R`. Fails like
+    /// [`StackOperation::SwapDeep`].
+    Roll,
+}
+
+impl Command {
+    /// How the command is written, leaving out the body of a loop, for [`Stats`].
+    fn kind(&self) -> String {
+        match self {
+            Command::Loop(_) => "[".into(),
+            Command::Conditional(_) => "?[".into(),
+            Command::While(_) => "L[".into(),
+            command => format!("{command}"),
+        }
+    }
+}
+
+/// Writes the command back out as FXYT source, in its canonical uppercase form.
+impl Display for Command {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Command::Coordinates(c) => write!(
+                f,
+                "{}",
+                match c {
+                    Coordinates::X => 'X',
+                    Coordinates::Y => 'Y',
+                    Coordinates::T => 'T',
+                }
+            ),
+            Command::Integer => write!(f, "N"),
+            // there's no way to write a negative number directly, so negate it in place, except
+            // for the one value whose opposite doesn't fit
+            Command::Literal(isize::MIN) => write!(f, "N{}~N1-", isize::MAX),
+            Command::Literal(value) if *value < 0 => write!(f, "N{}~", value.unsigned_abs()),
+            Command::Literal(value) => write!(f, "N{value}"),
+            Command::Digit(d) => write!(f, "{d}"),
+            Command::Arithmetic(a) => write!(
+                f,
+                "{}",
+                match a {
+                    Arithmetic::Plus => '+',
+                    Arithmetic::Minus => '-',
+                    Arithmetic::Times => '*',
+                    Arithmetic::Divide => '/',
+                    Arithmetic::Modulus => '%',
+                    Arithmetic::Power => 'E',
+                }
+            ),
+            Command::Mode => write!(f, "M"),
+            Command::Comparison(c) => write!(
+                f,
+                "{}",
+                match c {
+                    Comparison::Equals => "=",
+                    Comparison::LessThan => "<",
+                    Comparison::GreaterThan => ">",
+                    Comparison::AtMost => "<=",
+                    Comparison::AtLeast => ">=",
+                }
+            ),
+            Command::Invert => write!(f, "!"),
+            Command::Bitwise(b) => write!(
+                f,
+                "{}",
+                match b {
+                    Bitwise::Xor => "^",
+                    Bitwise::And => "&",
+                    Bitwise::Or => "|",
+                    Bitwise::ShiftLeft => "(",
+                    Bitwise::ShiftRight => ")",
+                    Bitwise::ShiftRightLogical => "')",
+                }
+            ),
+            Command::Clip => write!(f, "C"),
+            Command::SmoothStep => write!(f, "\""),
+            Command::EaseInOut => write!(f, "\\"),
+            Command::StackOperation(so) => write!(
+                f,
+                "{}",
+                match so {
+                    StackOperation::Duplicate => "D",
+                    StackOperation::DuplicatePair => "'D",
+                    StackOperation::Pop => "P",
+                    StackOperation::Swap => "S",
+                    StackOperation::Rotate => "R",
+                    StackOperation::RotateBack => "'R",
+                    StackOperation::Over => "O",
+                    StackOperation::SwapDeep => "J",
+                    StackOperation::Roll => "`",
+                }
+            ),
+            Command::Loop(inner_commands) => {
+                write!(f, "[")?;
+                write_commands(f, inner_commands)?;
+                write!(f, "]")
+            }
+            Command::While(inner_commands) => {
+                write!(f, "L[")?;
+                write_commands(f, inner_commands)?;
+                write!(f, "]")
+            }
+            Command::Conditional(inner_commands) => {
+                write!(f, "?[")?;
+                write_commands(f, inner_commands)?;
+                write!(f, "]")
+            }
+            Command::BitReverse => write!(f, "B"),
+            Command::DigitalRoot => write!(f, "U"),
+            Command::Spiral => write!(f, "V"),
+            Command::Accumulate => write!(f, "_"),
+            Command::FrameInterval => write!(f, "F"),
+            Command::Debug => write!(f, "W"),
+            Command::Absolute => write!(f, "A"),
+            Command::Negate => write!(f, "~"),
+            Command::SquareRoot => write!(f, "Q"),
+            Command::Clear => write!(f, "Z"),
+            Command::Depth => write!(f, "@"),
+            Command::Min => write!(f, "{{"),
+            Command::Max => write!(f, "}}"),
+            Command::Hash => write!(f, "H"),
+            Command::Store(register) => write!(f, ":{register}"),
+            Command::Load(register) => write!(f, ";{register}"),
+            Command::Gcd => write!(f, "G"),
+            Command::Clamp => write!(f, "K"),
+            Command::ModPow => write!(f, "I"),
+            Command::ScaledTime => write!(f, "'T"),
+            Command::Width => write!(f, ","),
+            Command::Height => write!(f, "."),
+        }
+    }
+}
+
+/// Writes the program back out as FXYT source with no whitespace or comments. Parsing the
+/// result gives back the same program, unless it has been through [`Program::optimize`], in
+/// which case it gives back one that renders the same, within the same
+/// [`max_stack`](RenderOptions::max_stack). The exception is a [`Command::Literal`] of
+/// `isize::MIN` put there by hand, which is written as `N9223372036854775807~N1-` and so
+/// needs one more stack slot than pushing it did.
+impl Display for Program {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write_commands(f, &self.commands)
+    }
+}
+
+/// Writes commands one after another, with a space wherever a `<` or `>` followed by `=` would
+/// otherwise read back as `<=` or `>=`.
+fn write_commands(f: &mut impl core::fmt::Write, commands: &[Command]) -> core::fmt::Result {
+    let mut previous: Option<&Command> = None;
+    for command in commands {
+        if let (
+            Some(Command::Comparison(Comparison::LessThan | Comparison::GreaterThan)),
+            Command::Comparison(Comparison::Equals | Comparison::AtMost | Comparison::AtLeast),
+        ) = (previous, command)
+        {
+            write!(f, " ")?;
+        }
+        write!(f, "{command}")?;
+        previous = Some(command);
+    }
+    Ok(())
+}
+
+/// Lays out `commands` for [`format`], `indent` levels deep.
+fn format_commands(out: &mut String, commands: &[Command], indent: usize) {
+    let mut rest = commands;
+    while !rest.is_empty() {
+        // everything up to the next loop with a body goes on one line
+        let block = rest.iter().enumerate().find_map(|(index, command)| {
+            let (opener, body) = match command {
+                Command::Loop(body) => ("[", body),
+                Command::While(body) => ("L[", body),
+                Command::Conditional(body) => ("?[", body),
+                _ => return None,
+            };
+            (!body.is_empty()).then_some((index, opener, body))
+        });
+        let line_end = block.map_or(rest.len(), |(index, _, _)| index);
+
+        out.extend(core::iter::repeat_n("    ", indent));
+        // writing to a String can't fail
+        let _ = write_commands(out, &rest[..line_end]);
+        match block {
+            Some((index, opener, body)) => {
+                out.push_str(opener);
+                out.push('\n');
+                format_commands(out, body, indent + 1);
+                out.extend(core::iter::repeat_n("    ", indent));
+                out.push_str("]\n");
+                rest = &rest[index + 1..];
+            }
+            None => {
+                out.push('\n');
+                rest = &[];
+            }
+        }
+    }
+}
+
+/// A position on the canvas and in time, with the origin at the bottom left.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Coords {
+    pub x: isize,
+    pub y: isize,
+    pub t: isize,
+}
+
+impl Coords {
+    fn new(x: usize, y: usize, t: usize) -> Self {
+        Self {
+            x: x as isize,
+            y: y as isize,
+            t: t as isize,
+        }
+    }
+
+    /// How far the coordinates are from (128, 128) on each axis, widened so that squaring
+    /// them can't overflow, however far out they are.
+    fn offset(&self) -> (i128, i128) {
+        (self.x as i128 - 128, self.y as i128 - 128)
+    }
+
+    /// Distance from (128, 128) in pixels, rounded down. Wraps if it doesn't fit in an `isize`.
+    fn radius(&self) -> isize {
+        let (dx, dy) = self.offset();
+        (dx.unsigned_abs().pow(2) + dy.unsigned_abs().pow(2)).isqrt() as isize
+    }
+
+    /// Angle around (128, 128) in 256ths of a turn, counter-clockwise
+    /// from the positive X axis. Uses an integer approximation of atan2 (good to well under a
+    /// unit) so it stays deterministic everywhere.
+    fn angle(&self) -> isize {
+        let (dx, dy) = self.offset();
+        if dx == 0 && dy == 0 {
+            return 0;
+        }
+
+        // atan of a ratio in 0..=1, scaled so that pi/4 is 32 units
+        fn octant(opposite: i128, adjacent: i128) -> i128 {
+            const ONE: i128 = 1 << 16;
+            let ratio = opposite * ONE / adjacent;
+            let correction = ratio * (ONE - ratio) / ONE * (653_400 + 177_000 * ratio / ONE) / ONE;
+            (32 * ratio + correction + ONE / 2) / ONE
+        }
+
+        let (ax, ay) = (dx.abs(), dy.abs());
+        let quadrant_angle = if ay <= ax {
+            octant(ay, ax)
+        } else {
+            64 - octant(ax, ay)
+        } as isize;
+
+        match (dx >= 0, dy >= 0) {
+            (true, true) => quadrant_angle,
+            (false, true) => 128 - quadrant_angle,
+            (false, false) => 128 + quadrant_angle,
+            (true, false) => 256 - quadrant_angle,
+        }
+        .rem_euclid(256)
+    }
+
+    /// Mixes the coordinates and `seed` into a pseudo-random value in `0..256`, using the
+    /// SplitMix64 finalizer on each in turn. The same inputs always give the same value.
+    fn hash(&self, seed: isize) -> isize {
+        fn mix(value: u64) -> u64 {
+            let value = value.wrapping_add(0x9E37_79B9_7F4A_7C15);
+            let value = (value ^ (value >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+            let value = (value ^ (value >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+            value ^ (value >> 31)
+        }
+
+        let hash = [self.x, self.y, self.t]
+            .iter()
+            .fold(mix(seed as u64), |hash, &value| mix(hash ^ value as u64));
+        (hash >> 56) as isize
+    }
+}
+
+impl Display for Coords {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "({}, {}, {})", self.x, self.y, self.t)
+    }
+}
+
+#[derive(Error, PartialEq, Eq, Debug)]
+pub enum FxytError {
+    #[error(
+        "{} at {coords} greater than 255 or less than 0",
+        describe_colour(*.red, *.green, *.blue, *.alpha)
+    )]
+    RgbOutOfRange {
+        coords: Coords,
+        red: isize,
+        green: isize,
+        blue: isize,
+        /// Only read by [`render_rgba8`], when there's a value for it.
+        alpha: Option<isize>,
+    },
+    #[error("{error} at {coords}{}", describe_position(*.pos))]
+    Runtime {
+        error: RuntimeError,
+        coords: Coords,
+        /// Where the command that failed starts in the source. `None` for programs that
+        /// weren't compiled from source, like deserialized ones.
+        pos: Option<usize>,
+    },
+    #[error("Failed to parse command")]
+    Parse(#[from] ParseError),
+    #[cfg(feature = "apng")]
+    #[error("Failed to encode image: {0}")]
+    Encoding(String),
+}
+
+/// Something in a program that's probably a mistake, though it doesn't stop it rendering. See
+/// [`ProgramInfo::warnings`].
+#[derive(Error, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Warning {
+    /// The commands after a `W`, or after dividing by a literal 0, in the same loop body or
+    /// at the top level, never run, since the program always stops there. For `W` that's only
+    /// if [`RenderOptions::debug_halt`] is on, as it is by default.
+    #[error("Found commands after one that always stops the program{}", describe_position(*.pos))]
+    Unreachable {
+        /// Where the first command that never runs starts in the source. `None` for programs
+        /// that weren't compiled from source, like deserialized ones.
+        pos: Option<usize>,
+    },
+}
+
+fn describe_position(pos: Option<usize>) -> String {
+    match pos {
+        Some(pos) => format!(", from the command at position `{pos}`"),
+        None => String::new(),
+    }
+}
+
+/// Something that went wrong while running a command, see [`FxytError::Runtime`].
+#[derive(Error, PartialEq, Eq, Debug)]
+pub enum RuntimeError {
+    #[error("Attempt to push more values to the stack than it can hold, 8 by default")]
+    StackOverflow,
+    #[error("Attempt to read from an empty stack")]
+    StackEmpty,
+    #[error("Attempt to divide by zero in mode 0")]
+    DivideByZero,
+    #[error("Attempt to take a remainder modulo zero in mode 0")]
+    ModulusByZero,
+    #[error("Bit width less than 0 or greater than the width of a stack value")]
+    BitWidthOutOfRange,
+    #[error("Attempt to set a frame interval less than 0 or greater than u32::MAX")]
+    FrameIntervalOutOfRange,
+    #[error("Arithmetic result too large to fit in a stack value")]
+    IntegerOverflow,
+    #[error("Attempt to increment mode beyond 2")]
+    ModeOutOfRange,
+    #[error("Debug command executed, output halted")]
+    DebugHalt,
+    #[error("Attempt to take the square root of a negative number")]
+    NegativeSqrt,
+    #[error("Attempt to raise a number to a negative power")]
+    NegativeExponent,
+    #[error("While loop ran more than 4096 times")]
+    IterationLimit,
+    #[error("Ran more commands for one pixel than the budget allows")]
+    OpBudgetExceeded,
+    #[error("Attempt to clamp to a range whose lower bound is above its upper bound")]
+    ClampBoundsInverted,
+    #[error("Attempt to use a register other than 0 to 3")]
+    RegisterOutOfRange,
+}
+
+fn describe_colour(red: isize, green: isize, blue: isize, alpha: Option<isize>) -> String {
+    match alpha {
+        Some(alpha) => format!("RGBA value ({red}, {green}, {blue}, {alpha})"),
+        None => format!("RGB value ({red}, {green}, {blue})"),
+    }
+}
+
+#[derive(Error, PartialEq, Eq, Debug)]
+pub enum ParseError {
+    /// Where the character is in the source. For a character that's only wrong because of the
+    /// one before it, like the `G` in `$G`, that's both of them.
+    #[error(
+        "Found character that is not a valid FXYT command at position `{}`",
+        .0.start
+    )]
+    InvalidCharacter(Range<usize>),
+    #[error("Found a bracket with no partner at position `{0}`")]
+    BracketMismatch(usize),
+    #[error("Attempt to enter a loop more than 8 levels deep")]
+    LoopNesting,
+    #[error("Found a hex literal too large for a stack value at position `{0}`")]
+    LiteralTooLarge(usize),
+}
+
+impl ParseError {
+    /// Where the error starts in the source, if it's anywhere in particular.
+    fn position(&self) -> Option<usize> {
+        match self {
+            ParseError::InvalidCharacter(span) => Some(span.start),
+            ParseError::BracketMismatch(pos) | ParseError::LiteralTooLarge(pos) => Some(*pos),
+            ParseError::LoopNesting => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{parse, Command, Coords, FxytError, ParseError, RuntimeError};
+    use rgb::RGB8;
+    use Command::*;
+
+    /// Evaluates a program at the origin and returns the resulting colour.
+    fn pixel(program: &str) -> Result<RGB8, FxytError> {
+        crate::render_pixel(program, 0, 0, 0)
+    }
+
+    #[test]
+    fn errors_compare() {
+        assert_eq!(
+            pixel("N1N0/"),
+            Err(FxytError::Runtime {
+                error: RuntimeError::DivideByZero,
+                coords: Coords { x: 0, y: 0, t: 0 },
+                pos: Some(4),
+            })
+        );
+        assert_eq!(
+            pixel("XY?"),
+            Err(FxytError::Parse(ParseError::InvalidCharacter(2..3)))
+        );
+        assert_ne!(
+            crate::validate("X]").unwrap_err(),
+            ParseError::InvalidCharacter(0..1).into()
+        );
+    }
+    #[test]
+    #[ignore = "file i/o"]
+    #[cfg(feature = "std")]
+    fn manual_render_check() {
+        use crate::{render, write_ppm};
+        use std::fs::File;
+        let output = render("XY^").unwrap();
+        write_ppm(&output[0], File::create("output.ppm").unwrap()).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn ppm_header_and_length() {
+        use crate::{render_sized, write_ppm};
+        let mut ppm = Vec::new();
+        write_ppm(&render_sized("XY^", 256, 256).unwrap()[0], &mut ppm).unwrap();
+        let header = b"P6\n256 256\n255\n";
+        assert_eq!(ppm[..header.len()], *header);
+        assert_eq!(ppm.len(), header.len() + 256 * 256 * 3);
+
+        let mut ppm = Vec::new();
+        write_ppm(&render_sized("XY^", 3, 2).unwrap()[0], &mut ppm).unwrap();
+        let (header, pixels) = ppm.split_at(b"P6\n3 2\n255\n".len());
+        assert_eq!(header, b"P6\n3 2\n255\n");
+        // a lone value on the stack is blue, and the top row comes first
+        assert_eq!(
+            pixels,
+            [0, 0, 1, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 1, 0, 0, 2]
+        );
+    }
+
+    #[test]
+    fn loop_parsing() {
+        let program = "NN5[N10+]";
+        assert_eq!(
+            (
+                9,
+                vec![
+                    Integer,
+                    Integer,
+                    Digit(5),
+                    Loop(vec![
+                        Integer,
+                        Digit(1),
+                        Digit(0),
+                        Arithmetic(crate::Arithmetic::Plus)
+                    ])
+                ]
+            ),
+            parse(program.as_bytes(), 0, 0, &mut Vec::new()).unwrap()
+        )
+    }
+    #[test]
+    fn loop_parsing_doubly_nested() {
+        let program = "NN5[N10[N4+]]";
+        assert_eq!(
+            (
+                13,
+                vec![
+                    Integer,
+                    Integer,
+                    Digit(5),
+                    Loop(vec![
+                        Integer,
+                        Digit(1),
+                        Digit(0),
+                        Loop(vec![Integer, Digit(4), Arithmetic(crate::Arithmetic::Plus)])
+                    ])
+                ]
+            ),
+            parse(program.as_bytes(), 0, 0, &mut Vec::new()).unwrap()
+        )
+    }
+    #[test]
+    fn loop_parsing_many_empty_nested() {
+        let program = "[[[[]]]]";
+        assert_eq!(
+            (8, vec![Loop(vec![Loop(vec![Loop(vec![Loop(vec![])])])])]),
+            parse(program.as_bytes(), 0, 0, &mut Vec::new()).unwrap()
+        )
+    }
+    #[test]
+    fn loop_parsing_twice_empty_nested() {
+        let program = "[[]]";
+        assert_eq!(
+            (4, vec![Loop(vec![Loop(vec![])])]),
+            parse(program.as_bytes(), 0, 0, &mut Vec::new()).unwrap()
+        )
+    }
+    #[test]
+    fn render_bytes_matches_str() {
+        use crate::{render, render_bytes};
+        let program = "XY^N128%N3[D]";
+        let from_str = render(program).unwrap();
+        let from_bytes = render_bytes(program.as_bytes()).unwrap();
+        assert_eq!(from_str.len(), from_bytes.len());
+        assert_eq!(from_str[0].image, from_bytes[0].image);
+    }
+    #[test]
+    fn parse_all() {
+        use crate::{parse_all, Program};
+        let (program, errors) = parse_all("X?Y]é");
+        assert_eq!(
+            errors,
+            [
+                ParseError::InvalidCharacter(1..2),
+                ParseError::InvalidCharacter(3..4),
+                ParseError::InvalidCharacter(4..6),
+            ]
+        );
+        assert_eq!(program, Program::compile("XY").unwrap());
+
+        let (program, errors) = parse_all("N5[N1é+ $8000000000000000 Y");
+        assert_eq!(
+            errors,
+            [
+                ParseError::BracketMismatch(2),
+                ParseError::InvalidCharacter(5..7),
+                ParseError::LiteralTooLarge(9),
+            ]
+        );
+        assert_eq!(program, Program::compile("N5 N1+ Y").unwrap());
+        // positions still point into the original source
+        let (program, _) = parse_all("X?é?N0/");
+        assert_eq!(
+            program.render_pixel(0, 0, 0).unwrap_err(),
+            FxytError::Runtime {
+                error: RuntimeError::DivideByZero,
+                coords: Coords { x: 0, y: 0, t: 0 },
+                pos: Some(7),
+            }
+        );
+
+        let (program, errors) = parse_all("XY^DD");
+        assert!(errors.is_empty());
+        assert_eq!(program, Program::compile("XY^DD").unwrap());
+        let (program, errors) = parse_all(&"[".repeat(9));
+        assert_eq!(errors.last(), Some(&ParseError::LoopNesting));
+        assert_eq!(program.info().command_count, 0);
+    }
+    #[test]
+    fn invalid_character_spans() {
+        let span = |program: &str| match crate::Program::compile(program) {
+            Err(ParseError::InvalidCharacter(span)) => span,
+            other => panic!("{other:?}"),
+        };
+        // a hex literal without any digits covers the $ and what's there instead
+        assert_eq!(span("XY$Z"), 2..4);
+        assert_eq!(span("XY $é"), 3..6);
+        assert_eq!(span("XY$"), 2..3);
+        assert_eq!(span("XY]"), 2..3);
+        assert_eq!(span("N1[X]]"), 5..6);
+        assert_eq!(span("N1:9"), 2..4);
+        assert_eq!(span("€"), 0..3);
+        // messages still give where it starts
+        assert_eq!(
+            ParseError::InvalidCharacter(2..4).to_string(),
+            "Found character that is not a valid FXYT command at position `2`"
+        );
+    }
+    #[test]
+    fn render_bytes_rejects_non_ascii() {
+        use crate::render_bytes;
+        assert_eq!(
+            render_bytes(&[b'X', b'Y', 0xC3, b'^']).unwrap_err(),
+            FxytError::Parse(ParseError::InvalidCharacter(2..3))
+        );
+    }
+    #[test]
+    fn accumulate_in_counted_loop() {
+        use crate::render;
+        // sum 1..=5 into the bottom slot, then discard the counter and pad out green and blue
+        let output = render("N0N1N5[D_N1+]PN0N0").unwrap();
+        assert_eq!(output[0].image[0][0], RGB8::new(15, 0, 0));
+    }
+    #[test]
+    fn loop_counts() {
+        assert_eq!(pixel("N0N3[N2+]N0N0").unwrap(), RGB8::new(6, 0, 0));
+        assert_eq!(pixel("N7N0[N2+]N0N0").unwrap(), RGB8::new(7, 0, 0));
+        assert_eq!(pixel("N7N0N3-[N2+]N0N0").unwrap(), RGB8::new(7, 0, 0));
+        // the body's own counter is popped fresh on every pass of the outer loop
+        assert_eq!(pixel("N0N3[N4[N1+]]N0N0").unwrap(), RGB8::new(12, 0, 0));
+        // overflow is caught on the pass that causes it, not just at the end
+        assert!(matches!(
+            pixel("N1N1N1N1N1N9[D]"),
+            Err(FxytError::Runtime {
+                error: RuntimeError::StackOverflow,
+                ..
+            })
+        ));
+    }
+    #[test]
+    fn conditional() {
+        assert_eq!(pixel("N1?[N5]N0N0").unwrap(), RGB8::new(5, 0, 0));
+        assert_eq!(pixel("N7N0?[N5+]N0N0").unwrap(), RGB8::new(7, 0, 0));
+        assert_eq!(pixel("N7N0N1-?[N5+]N0N0").unwrap(), RGB8::new(12, 0, 0));
+        // add 10 on the odd passes of a loop
+        assert_eq!(
+            pixel("N0N1N4[DN2%?[SN10+S]N1+]PN0N0").unwrap(),
+            RGB8::new(20, 0, 0)
+        );
+        // a body that picks the colour itself
+        assert_eq!(
+            crate::render_pixel("XN100>?[MN1N0/]N0N0N0", 150, 0, 0).unwrap(),
+            RGB8::new(0, 0, 0)
+        );
+        assert!(matches!(
+            pixel("?[N1]"),
+            Err(FxytError::Runtime {
+                error: RuntimeError::StackEmpty,
+                ..
+            })
+        ));
+    }
+    #[test]
+    fn while_loop() {
+        // sum 5 + 4 + ... + 1 into the bottom slot
+        assert_eq!(
+            pixel("N0N5N1L[D_N1-DN0>]PN0N0").unwrap(),
+            RGB8::new(15, 0, 0)
+        );
+        // halve until odd
+        assert_eq!(
+            pixel("N96DN2%!L[N2/DN2%!]N0N0").unwrap(),
+            RGB8::new(3, 0, 0)
+        );
+        assert_eq!(pixel("N7N0L[N1]N0N0").unwrap(), RGB8::new(7, 0, 0));
+        assert_eq!(
+            parse(b"l[N0]", 0, 0, &mut Vec::new()).unwrap(),
+            (5, vec![While(vec![Integer, Digit(0)])])
+        );
+
+        assert!(matches!(
+            pixel("N1L[N1]"),
+            Err(FxytError::Runtime {
+                error: RuntimeError::IterationLimit,
+                ..
+            })
+        ));
+        // 4096 passes is fine, one more isn't
+        assert_eq!(
+            pixel("N0N1L[N1+DN4096<]N4096=N0N0").unwrap(),
+            RGB8::new(1, 0, 0)
+        );
+        assert!(matches!(
+            pixel("N0N1L[N1+DN4097<]"),
+            Err(FxytError::Runtime {
+                error: RuntimeError::IterationLimit,
+                ..
+            })
+        ));
+    }
+    #[test]
+    fn conditional_parsing() {
+        assert_eq!(
+            parse(b"N1?[X?[]]", 0, 0, &mut Vec::new()).unwrap(),
+            (
+                9,
+                vec![
+                    Integer,
+                    Digit(1),
+                    Conditional(vec![
+                        Coordinates(crate::Coordinates::X),
+                        Conditional(vec![])
+                    ])
+                ]
+            )
+        );
+        // the bracket has to come straight after
+        assert_eq!(
+            parse(b"N1? [X]", 0, 0, &mut Vec::new()).unwrap_err(),
+            ParseError::InvalidCharacter(2..3)
+        );
+        assert!(matches!(
+            parse(b"N1?[X", 0, 0, &mut Vec::new()),
+            Err(ParseError::BracketMismatch(3))
+        ));
+        assert!(matches!(
+            parse(b"?[?[?[?[?[?[?[?[?[]]]]]]]]]", 0, 0, &mut Vec::new()),
+            Err(ParseError::LoopNesting)
+        ));
+    }
+    #[test]
+    fn accumulate_without_base_errors() {
+        use crate::render;
+        assert!(matches!(
+            render("N5_"),
+            Err(FxytError::Runtime {
+                error: RuntimeError::StackEmpty,
+                ..
+            })
+        ));
+        assert!(matches!(
+            render("_"),
+            Err(FxytError::Runtime {
+                error: RuntimeError::StackEmpty,
+                ..
+            })
+        ));
+    }
+    #[test]
+    fn row_and_column_match_full_render() {
+        use crate::{render, render_column, render_row};
+        let program = "XY^XY&N2/N64%";
+        let frame = render(program).unwrap().remove(0).image;
+
+        assert_eq!(render_row(program, 37, 0).unwrap()[..], frame[255 - 37]);
+
+        let column = render_column(program, 200, 0).unwrap();
+        for y in 0..256 {
+            assert_eq!(column[y], frame[255 - y][200]);
+        }
+    }
+    #[test]
+    fn bit_reverse_width_4() {
+        assert_eq!(pixel("N1N4BN0N0").unwrap(), RGB8::new(8, 0, 0));
+        assert_eq!(pixel("N6N4BN0N0").unwrap(), RGB8::new(6, 0, 0));
+        assert_eq!(pixel("N13N4BN0N0").unwrap(), RGB8::new(11, 0, 0));
+        // bits above the width are untouched
+        assert_eq!(pixel("N241N4BN0N0").unwrap(), RGB8::new(248, 0, 0));
+    }
+    #[test]
+    fn bit_reverse_width_8() {
+        assert_eq!(pixel("N1N8BN0N0").unwrap(), RGB8::new(128, 0, 0));
+        assert_eq!(pixel("N240N8BN0N0").unwrap(), RGB8::new(15, 0, 0));
+        assert_eq!(pixel("N160N8BN0N0").unwrap(), RGB8::new(5, 0, 0));
+    }
+    #[test]
+    fn bit_reverse_invalid_width() {
+        assert!(matches!(
+            pixel("N1N65B"),
+            Err(FxytError::Runtime {
+                error: RuntimeError::BitWidthOutOfRange,
+                ..
+            })
+        ));
+        assert!(matches!(
+            pixel("N1N0N1-B"),
+            Err(FxytError::Runtime {
+                error: RuntimeError::BitWidthOutOfRange,
+                ..
+            })
+        ));
+    }
+    #[test]
+    fn seamless_loop_closes_the_gap() {
+        use crate::{render_with, RenderOptions};
+        fn loop_gap(seamless: bool) -> u8 {
+            let options = RenderOptions {
+                seamless,
+                ..Default::default()
+            };
+            let frames = render_with("TN0N0", &options).unwrap();
+            let first = frames.first().unwrap().image[0][0].r;
+            let last = frames.last().unwrap().image[0][0].r;
+            first.abs_diff(last)
+        }
+
+        assert_eq!(loop_gap(false), 255);
+        assert_eq!(loop_gap(true), 1);
+    }
+    #[test]
+    fn digital_root() {
+        assert_eq!(pixel("N0UN0N0").unwrap(), RGB8::new(0, 0, 0));
+        assert_eq!(pixel("N9UN0N0").unwrap(), RGB8::new(9, 0, 0));
+        assert_eq!(pixel("N38UN0N0").unwrap(), RGB8::new(2, 0, 0));
+        assert_eq!(pixel("N9875UN0N0").unwrap(), RGB8::new(2, 0, 0));
+        assert_eq!(pixel("N123456UN0N0").unwrap(), RGB8::new(3, 0, 0));
+        assert_eq!(pixel("N0N38-UN0N0").unwrap(), RGB8::new(2, 0, 0));
+    }
+    #[test]
+    fn absolute() {
+        assert_eq!(pixel("N7AN0AN0N7-A").unwrap(), RGB8::new(7, 0, 7));
+        // distance from the middle of the canvas
+        assert_eq!(
+            crate::render_pixel("XN128-ADD", 28, 0, 0).unwrap(),
+            RGB8::new(100, 100, 100)
+        );
+        assert!(matches!(
+            pixel("N0N9223372036854775807-N1-A"),
+            Err(FxytError::Runtime {
+                error: RuntimeError::IntegerOverflow,
+                ..
+            })
+        ));
+    }
+    #[test]
+    fn negate() {
+        assert_eq!(pixel("N0~N5~~N0N7-~").unwrap(), RGB8::new(0, 5, 7));
+        assert_eq!(
+            pixel("N9223372036854775807~N9223372036854775807+").unwrap(),
+            RGB8::new(0, 0, 0)
+        );
+        assert!(matches!(
+            pixel("N9223372036854775807~N1-~"),
+            Err(FxytError::Runtime {
+                error: RuntimeError::IntegerOverflow,
+                ..
+            })
+        ));
+    }
+    #[test]
+    fn square_root() {
+        assert_eq!(pixel("N0QN1QN144Q").unwrap(), RGB8::new(0, 1, 12));
+        assert_eq!(pixel("N2QN99QN65535Q").unwrap(), RGB8::new(1, 9, 255));
+        // radial gradient out from the middle of the canvas
+        assert_eq!(
+            crate::render_pixel("XN128-D*YN128-D*+QDD", 158, 168, 0).unwrap(),
+            RGB8::new(50, 50, 50)
+        );
+        assert!(matches!(
+            pixel("N0N1-Q"),
+            Err(FxytError::Runtime {
+                error: RuntimeError::NegativeSqrt,
+                ..
+            })
+        ));
+    }
+    #[test]
+    fn power() {
+        assert_eq!(pixel("N2N7EN3N5EN15N2E").unwrap(), RGB8::new(128, 243, 225));
+        assert_eq!(pixel("N0N0EN255N0EN0N3-N0E").unwrap(), RGB8::new(1, 1, 1));
+        assert_eq!(
+            pixel("N0N3-N3EN0S-N0N1EN1N99999999999E").unwrap(),
+            RGB8::new(27, 0, 1)
+        );
+        // only the parity of a huge exponent matters for -1
+        assert_eq!(
+            pixel("N0N1-N99999999999EN0S-N0N0").unwrap(),
+            RGB8::new(1, 0, 0)
+        );
+        assert!(matches!(
+            pixel("N2N64E"),
+            Err(FxytError::Runtime {
+                error: RuntimeError::IntegerOverflow,
+                ..
+            })
+        ));
+        assert!(matches!(
+            pixel("N2N99999999999E"),
+            Err(FxytError::Runtime {
+                error: RuntimeError::IntegerOverflow,
+                ..
+            })
+        ));
+        assert!(matches!(
+            pixel("N2N0N1-E"),
+            Err(FxytError::Runtime {
+                error: RuntimeError::NegativeExponent,
+                ..
+            })
+        ));
+        assert!(matches!(
+            pixel("N0N0N1-E"),
+            Err(FxytError::Runtime {
+                error: RuntimeError::NegativeExponent,
+                ..
+            })
+        ));
+    }
+    #[test]
+    fn shifts() {
+        assert_eq!(
+            crate::render_pixel("XN1(N0N0", 100, 0, 0).unwrap(),
+            RGB8::new(200, 0, 0)
+        );
+        assert_eq!(pixel("N200N3)N1N4(N0N1(").unwrap(), RGB8::new(25, 16, 0));
+        // arithmetic, so the sign is kept
+        assert_eq!(pixel("N0N8-N2)N0S-N0N0").unwrap(), RGB8::new(2, 0, 0));
+        // negative amounts don't shift, over-large ones shift everything out
+        assert_eq!(pixel("N5N0N3-(N0N0").unwrap(), RGB8::new(5, 0, 0));
+        assert_eq!(pixel("N5N64(N5N999)N0N0").unwrap(), RGB8::new(0, 0, 0));
+        assert_eq!(pixel("N0N5-N999)N1+N0N0").unwrap(), RGB8::new(0, 0, 0));
+    }
+    #[test]
+    fn logical_shift() {
+        // the same negative value shifted both ways: arithmetic keeps the sign bits, logical
+        // shifts in zeroes
+        assert_eq!(
+            pixel("N0N8-N62)N0S- N0N8-N62') N0N8-N1')N0>").unwrap(),
+            RGB8::new(1, 3, 1)
+        );
+        assert_eq!(pixel("N200N3') N0N1') N0").unwrap(), RGB8::new(25, 0, 0));
+        assert_eq!(
+            pixel("N0N1-N64') N0N1-N3~')N1+ N0").unwrap(),
+            RGB8::new(0, 0, 0)
+        );
+        // ' on its own, or before something without a variant, isn't anything
+        assert_eq!(
+            parse(b"N1'X", 0, 0, &mut Vec::new()).unwrap_err(),
+            ParseError::InvalidCharacter(2..4)
+        );
+        assert_eq!(
+            parse(b"N1'", 0, 0, &mut Vec::new()).unwrap_err(),
+            ParseError::InvalidCharacter(2..3)
+        );
+    }
+    #[test]
+    fn duplicate_pair() {
+        assert_eq!(pixel("N1N2'DP").unwrap(), RGB8::new(1, 2, 1));
+        assert_eq!(
+            crate::render_pixel("XY'D+", 5, 3, 0).unwrap(),
+            RGB8::new(5, 3, 8)
+        );
+        for program in ["N1'D", "'D"] {
+            assert!(matches!(
+                pixel(program),
+                Err(FxytError::Runtime {
+                    error: RuntimeError::StackEmpty,
+                    ..
+                })
+            ));
+        }
+        // 8 values fit, but not 9
+        assert_eq!(pixel("N1N1N1N1N1N1'DPPPPP").unwrap(), RGB8::new(1, 1, 1));
+        assert!(matches!(
+            pixel("N1N1N1N1N1N1N1'D"),
+            Err(FxytError::Runtime {
+                error: RuntimeError::StackOverflow,
+                ..
+            })
+        ));
+    }
+    #[test]
+    fn rotate() {
+        // the third value comes up to the top
+        assert_eq!(pixel("N1N2N3R").unwrap(), RGB8::new(2, 3, 1));
+        assert_eq!(pixel("N1N2N3RRR").unwrap(), RGB8::new(1, 2, 3));
+        // the order of the other two doesn't change, unlike swapping the top and third
+        assert_eq!(pixel("N1N2N3SN2J").unwrap(), pixel("N1N2N3R").unwrap());
+        assert_eq!(pixel("N1N2N3N2J").unwrap(), RGB8::new(3, 2, 1));
+        assert_eq!(pixel("N9N1N2N3RP").unwrap(), RGB8::new(9, 2, 3));
+        // and goes back down again
+        assert_eq!(pixel("N1N2N3'R").unwrap(), RGB8::new(3, 1, 2));
+        assert_eq!(pixel("N1N2N3R'R").unwrap(), RGB8::new(1, 2, 3));
+        assert_eq!(pixel("N1N2N3'rR").unwrap(), RGB8::new(1, 2, 3));
+        for program in ["N1N2R", "N1N2'R"] {
+            assert!(matches!(
+                pixel(program),
+                Err(FxytError::Runtime {
+                    error: RuntimeError::StackEmpty,
+                    ..
+                })
+            ));
+        }
+    }
+    #[test]
+    fn over() {
+        assert_eq!(pixel("N1N2O").unwrap(), RGB8::new(1, 2, 1));
+        assert_eq!(pixel("N9N1N2OP").unwrap(), RGB8::new(9, 1, 2));
+        assert_eq!(pixel("N1N1N1N1N1N1N1O").unwrap(), RGB8::new(1, 1, 1));
+        assert!(matches!(
+            pixel("N1N1N1N1N1N1N1N1O"),
+            Err(FxytError::Runtime {
+                error: RuntimeError::StackOverflow,
+                ..
+            })
+        ));
+        assert!(matches!(
+            pixel("N1O"),
+            Err(FxytError::Runtime {
+                error: RuntimeError::StackEmpty,
+                ..
+            })
+        ));
+    }
+    #[test]
+    fn clear() {
+        assert_eq!(pixel("N1N2N3ZN4N5N6").unwrap(), RGB8::new(4, 5, 6));
+        assert_eq!(pixel("ZN1N1N1N1N1N1N1N1Z@").unwrap(), RGB8::new(0, 0, 0));
+        // lets a loop start from a clean slate on every pass
+        assert_eq!(pixel("N1N2N3N4[ZN7]N0N0").unwrap(), RGB8::new(7, 0, 0));
+        assert!(matches!(
+            pixel("N1N2ZP"),
+            Err(FxytError::Runtime {
+                error: RuntimeError::StackEmpty,
+                ..
+            })
+        ));
+    }
+    #[test]
+    fn depth() {
+        assert_eq!(pixel("@@@").unwrap(), RGB8::new(0, 1, 2));
+        assert_eq!(pixel("N5N5PP@N0N0").unwrap(), RGB8::new(0, 0, 0));
+        assert_eq!(pixel("N1N1N1N1N1N1N1@").unwrap(), RGB8::new(1, 1, 7));
+        assert!(matches!(
+            pixel("N1N1N1N1N1N1N1N1@"),
+            Err(FxytError::Runtime {
+                error: RuntimeError::StackOverflow,
+                ..
+            })
+        ));
+        // counts whatever a loop left behind
+        assert_eq!(pixel("N3[N0]@N0N0").unwrap(), RGB8::new(3, 0, 0));
+    }
+    #[test]
+    fn inclusive_comparisons() {
+        use crate::Comparison;
+        assert_eq!(
+            parse(b"<=>=<>= <", 0, 0, &mut Vec::new()).unwrap().1,
+            vec![
+                Command::Comparison(Comparison::AtMost),
+                Command::Comparison(Comparison::AtLeast),
+                Command::Comparison(Comparison::LessThan),
+                Command::Comparison(Comparison::AtLeast),
+                Command::Comparison(Comparison::LessThan),
+            ]
+        );
+        // a space keeps them apart
+        assert_eq!(
+            parse(b"< =", 0, 0, &mut Vec::new()).unwrap().1,
+            vec![
+                Command::Comparison(Comparison::LessThan),
+                Command::Comparison(Comparison::Equals),
+            ]
+        );
+        assert_eq!(pixel("N3N3<=N3N4<=N4N3<=").unwrap(), RGB8::new(1, 1, 0));
+        assert_eq!(pixel("N3N3>=N3N4>=N4N3>=").unwrap(), RGB8::new(1, 0, 1));
+        // error positions still line up after a two character command
+        assert_eq!(
+            parse(b"XY<=\xFF", 0, 0, &mut Vec::new()).unwrap_err(),
+            ParseError::InvalidCharacter(4..5)
+        );
+    }
+    #[test]
+    fn hsv_hue_sweep() {
+        use crate::{render_with, ColourMode, RenderOptions};
+        let options = RenderOptions {
+            colours: ColourMode::Hsv,
+            ..Default::default()
+        };
+        let frame = render_with("XN255N255", &options).unwrap().remove(0);
+        let row = &frame.image[0];
+        assert_eq!(row[0], RGB8::new(255, 0, 0));
+        assert_eq!(row[64], RGB8::new(127, 255, 0));
+        assert_eq!(row[128], RGB8::new(0, 255, 255));
+        assert_eq!(row[192], RGB8::new(128, 0, 255));
+        assert_eq!(row[255], RGB8::new(255, 0, 5));
+
+        // no saturation is grey, and value scales everything
+        let frame = render_with("XN0N200", &options).unwrap().remove(0);
+        assert_eq!(frame.image[0][100], RGB8::new(200, 200, 200));
+        let frame = render_with("N128N255N100", &options).unwrap().remove(0);
+        assert_eq!(frame.image[0][0], RGB8::new(0, 100, 100));
+
+        assert!(matches!(
+            render_with("N256N255N255", &options),
+            Err(FxytError::RgbOutOfRange { red: 256, .. })
+        ));
+    }
+    #[test]
+    fn y_down_flips_rows() {
+        use crate::{render_with, RenderOptions};
+        let up = render_with("YDD", &RenderOptions::default())
+            .unwrap()
+            .remove(0);
+        assert_eq!(up.image[0][0], RGB8::new(255, 255, 255));
+        assert_eq!(up.image[255][0], RGB8::new(0, 0, 0));
+
+        let options = RenderOptions {
+            y_down: true,
+            ..Default::default()
+        };
+        let down = render_with("YDD", &options).unwrap().remove(0);
+        assert_eq!(down.image[0][0], RGB8::new(0, 0, 0));
+        assert_eq!(down.image[255][0], RGB8::new(255, 255, 255));
+        assert!(down.image.iter().eq(up.image.iter().rev()));
+    }
+    #[test]
+    fn supersampling_softens_edges() {
+        use crate::{render_with, RenderOptions};
+        // white above the diagonal, black below
+        let program = "XY<N255*DD";
+        let sharp = render_with(program, &RenderOptions::default())
+            .unwrap()
+            .remove(0);
+        assert!(sharp.image.iter().flatten().all(|pixel| pixel.r % 255 == 0));
+
+        let options = RenderOptions {
+            samples: 4,
+            ..Default::default()
+        };
+        let smooth = render_with(program, &options).unwrap().remove(0);
+        // 6 of the 16 samples along the diagonal are above it
+        for x in [0, 100, 255] {
+            assert_eq!(smooth.image[255 - x][x], RGB8::new(96, 96, 96));
+        }
+        // away from the edge nothing changes
+        assert_eq!(smooth.image[0][0], sharp.image[0][0]);
+        assert_eq!(smooth.image[255][255], sharp.image[255][255]);
+    }
+    #[test]
+    fn easing() {
+        assert_eq!(pixel(r#"N0" N255" N128""#).unwrap(), RGB8::new(0, 255, 128));
+        assert_eq!(pixel(r"N0\ N255\ N128\").unwrap(), RGB8::new(0, 255, 129));
+        // slower than a straight line at the start, faster through the middle
+        assert_eq!(pixel(r#"N64" N64\ N0"#).unwrap(), RGB8::new(40, 16, 0));
+        assert_eq!(pixel(r#"N100" N100\ N0"#).unwrap(), RGB8::new(87, 62, 0));
+        // clipped first
+        assert_eq!(
+            pixel(r#"N0N5-" N300\ N0N1-\"#).unwrap(),
+            RGB8::new(0, 255, 0)
+        );
+        for ease in [crate::smooth_step, crate::ease_in_out] {
+            assert!((0..255).all(|x| ease(x) <= ease(x + 1)));
+            assert!((0..=255).all(|x| ease(x) + ease(255 - x) == 255));
+        }
+        assert!(matches!(
+            pixel("\""),
+            Err(FxytError::Runtime {
+                error: RuntimeError::StackEmpty,
+                ..
+            })
+        ));
+    }
+    #[test]
+    fn scaled_time() {
+        use crate::{render_with, RenderOptions};
+        let reds = |options: RenderOptions| {
+            let frames = render_with("'T N0N0", &options.size(1, 1)).unwrap();
+            frames
+                .iter()
+                .map(|frame| frame.image[0][0].r)
+                .collect::<Vec<_>>()
+        };
+        // the same span whatever the frame count
+        let short = reds(RenderOptions::new().frames(16));
+        assert_eq!(short.len(), 16);
+        assert_eq!((short[0], short[8], short[15]), (0, 128, 240));
+        let long = reds(RenderOptions::new().frames(1024));
+        assert_eq!(long.len(), 1024);
+        assert_eq!((long[0], long[512], long[1023]), (0, 128, 255));
+        assert_eq!(reds(RenderOptions::new()), (0..=255).collect::<Vec<_>>());
+        // counted from the first frame, not from T = 0
+        let late = reds(RenderOptions::new().frames(4).t_start(100));
+        assert_eq!(late, [0, 64, 128, 192]);
+
+        assert!(crate::Program::compile("'TN0N0").unwrap().is_animated());
+        assert_eq!(pixel("'t'TN0").unwrap(), RGB8::new(0, 0, 0));
+    }
+    #[test]
+    fn render_channels() {
+        use crate::render_channels;
+        let frames = render_channels("X", "Y", "XY+N2/").unwrap();
+        assert_eq!(frames.len(), 1);
+        let image = &frames[0].image;
+        assert_eq!(image[255][0], RGB8::new(0, 0, 0));
+        assert_eq!(image[0][255], RGB8::new(255, 255, 255));
+        assert_eq!(image[255 - 64][200], RGB8::new(200, 64, 132));
+        assert_eq!(image[0][0], RGB8::new(0, 255, 127));
+
+        // each program starts with an empty stack, in mode 0, whatever the others did
+        let frames = render_channels("N1N2N3 MM", "@", "N9").unwrap();
+        assert_eq!(frames[0].image[7][7], RGB8::new(3, 0, 9));
+        assert!(matches!(
+            render_channels("M", "N1N0/", "N0"),
+            Err(FxytError::Runtime {
+                error: RuntimeError::DivideByZero,
+                ..
+            })
+        ));
+        // stopping early with a colour gives that colour's channel
+        let frames = render_channels("MMN1N0/", "MMN1N0/", "MN1N0/").unwrap();
+        assert_eq!(frames[0].image[7][7], RGB8::new(255, 0, 0));
+
+        assert!(matches!(
+            render_channels("N1", "XN100+", "N2"),
+            Err(FxytError::RgbOutOfRange {
+                coords: Coords { x: 156, y: 0, t: 0 },
+                red: 1,
+                green: 256,
+                blue: 2,
+                alpha: None,
+            })
+        ));
+    }
+    #[test]
+    fn region_matches_full_render() {
+        use crate::{render, render_region};
+        let program = "XY^ YN3*N256% X";
+        let full = render(program).unwrap().remove(0);
+        let region = render_region(program, 40, 100, 30, 20, 0).unwrap();
+        assert_eq!((region.width(), region.height()), (30, 20));
+        // the region's top row is at Y = 119, which is row 136 of the full frame
+        for (row, full_row) in region.image.iter().zip(&full.image[136..156]) {
+            assert_eq!(row[..], full_row[40..70]);
+        }
+
+        // and for a region that's right up against the top right corner
+        let region = render_region(program, 250, 250, 6, 6, 0).unwrap();
+        assert_eq!(region.image[0][5], full.image[0][255]);
+        assert_eq!(region.image[5][0], full.image[5][250]);
+    }
+    #[test]
+    fn tiles_reassemble() {
+        use crate::{render_sized, render_tiles};
+        let program = "XY^ YN3*N256% X";
+        let full = render_sized(program, 100, 70).unwrap().remove(0);
+
+        let mut canvas = vec![vec![RGB8::default(); 100]; 70];
+        let mut tiles = 0;
+        render_tiles(program, 100, 70, 32, 0, &mut |x0, y0, tile| {
+            tiles += 1;
+            for (row, pixels) in tile.image.iter().enumerate() {
+                let y = y0 + tile.height() - 1 - row;
+                canvas[69 - y][x0..x0 + tile.width()].copy_from_slice(pixels);
+            }
+        })
+        .unwrap();
+        assert_eq!(tiles, 4 * 3);
+        assert_eq!(canvas, full.image);
+
+        assert!(matches!(
+            render_tiles("XN50=N1S/", 100, 100, 32, 0, &mut |_, _, _| {}),
+            Err(FxytError::Runtime {
+                error: RuntimeError::DivideByZero,
+                ..
+            })
+        ));
+    }
+    #[test]
+    fn options_builder() {
+        use crate::{render, render_sized, render_with, RenderOptions};
+        let options = RenderOptions::new().size(64, 32).y_down(true);
+        let frames = render_with("XYN4*D", &options).unwrap();
+        assert_eq!(frames.len(), 1);
+        assert_eq!((frames[0].width(), frames[0].height()), (64, 32));
+        assert_eq!(frames[0].image[0][10], RGB8::new(10, 0, 0));
+        assert_eq!(frames[0].image[31][10], RGB8::new(10, 124, 124));
+        assert_eq!(
+            render_with("XY^", &RenderOptions::new().size(100, 70)).unwrap(),
+            render_sized("XY^", 100, 70).unwrap()
+        );
+
+        let options = RenderOptions::new().frames(4).t_start(10).seamless(true);
+        let frames = render_with("TDD", &options).unwrap();
+        let ts: Vec<_> = frames.iter().map(|frame| frame.image[0][0].r).collect();
+        assert_eq!(ts, [10, 12, 13, 11]);
+
+        // the builder's defaults are the usual ones
+        assert_eq!(
+            render_with("XY^", &RenderOptions::new()).unwrap(),
+            render("XY^").unwrap()
+        );
+        assert!(matches!(
+            render_with("N1L[N1]", &RenderOptions::new().max_ops(100)),
+            Err(FxytError::Runtime {
+                error: RuntimeError::OpBudgetExceeded,
+                ..
+            })
+        ));
+    }
+    #[test]
+    #[cfg(feature = "parallel")]
+    fn frame_parallel_matches_serial() {
+        use crate::{
+            frame_times, render_all, render_canvas_serial, render_frames_parallel, Frame, Program,
+            RenderOptions,
+        };
+        let program = Program::compile("XT+N255& YTN3*+N255& XY^T^N255& TN10*F").unwrap();
+        let options = RenderOptions::new().size(64, 48).frames(12).seamless(true);
+        let serial: Vec<Frame> = frame_times::<RGB8>(&program, &options)
+            .into_iter()
+            .map(|t| render_canvas_serial(&program, (0, 0), 64, 48, t, &options, None).unwrap())
+            .collect();
+        assert_eq!(serial.len(), 12);
+        assert_eq!(render_frames_parallel(&program, &options).unwrap(), serial);
+        assert_eq!(render_all::<RGB8>(&program, &options).unwrap(), serial);
+    }
+    #[test]
+    fn stats_count_commands() {
+        use crate::{render_with, render_with_stats, RenderOptions};
+        // 8 pixels, each running the loop body three times
+        let options = RenderOptions::new().size(4, 2);
+        let (frames, stats) = render_with_stats("N3[XP] XYD", &options).unwrap();
+        assert_eq!(frames, render_with("N3[XP] XYD", &options).unwrap());
+        let counts: Vec<_> = stats
+            .counts
+            .iter()
+            .map(|(command, &count)| (command.as_str(), count))
+            .collect();
+        assert_eq!(
+            counts,
+            [
+                ("3", 8),
+                ("D", 8),
+                ("N", 8),
+                ("P", 24),
+                ("X", 32),
+                ("Y", 8),
+                ("[", 8)
+            ]
+        );
+        assert_eq!(stats.max_depth, 3);
+
+        // across frames too, and loops of every kind
+        let options = RenderOptions::new().size(1, 1).frames(3);
+        let (_, stats) = render_with_stats("T?[N0L[N0]] TN1<=DD", &options).unwrap();
+        assert_eq!(stats.counts["T"], 6);
+        assert_eq!(stats.counts["?["], 3);
+        assert_eq!(stats.counts["L["], 2);
+        assert_eq!(stats.counts["<="], 3);
+    }
+    #[test]
+    fn max_stack() {
+        use crate::{render_with, RenderOptions};
+        // ten values deep before the pops bring it back down
+        let program = "N1N2N3N4N5N6N7N8N9N10 PPPPPPP";
+        let render = |max_stack| render_with(program, &RenderOptions::new().max_stack(max_stack));
+        assert!(matches!(
+            render(8),
+            Err(FxytError::Runtime {
+                error: RuntimeError::StackOverflow,
+                pos: Some(16),
+                ..
+            })
+        ));
+        assert_eq!(render(16).unwrap()[0].image[0][0], RGB8::new(1, 2, 3));
+        assert_eq!(render(10).unwrap()[0].image[0][0], RGB8::new(1, 2, 3));
+        assert!(render(9).is_err());
+
+        // and less room than usual
+        let options = RenderOptions::new().max_stack(2);
+        assert!(render_with("XY", &options).is_ok());
+        assert!(render_with("XYD", &options).is_err());
+    }
+    #[test]
+    fn canvas_size_commands() {
+        use crate::{render_with, RenderOptions};
+        assert_eq!(pixel(",N1- .N1- N0").unwrap(), RGB8::new(255, 255, 0));
+        let sizes = |options: &RenderOptions| {
+            let frame = render_with(",N8/ .N8/ N0", options).unwrap().remove(0);
+            let pixel = frame.image[0][0];
+            (pixel.r, pixel.g)
+        };
+        assert_eq!(sizes(&RenderOptions::new()), (32, 32));
+        assert_eq!(sizes(&RenderOptions::new().size(100, 40)), (12, 5));
+        // supersampling makes the grid finer, so the sizes follow the coordinates
+        assert_eq!(
+            sizes(&RenderOptions::new().size(100, 40).samples(2)),
+            (25, 10)
+        );
+
+        // centring works out the same whatever the size
+        let centred = "X,N2/- A Y.N2/- A + N0N0";
+        let frame = render_with(centred, &RenderOptions::new().size(20, 10))
+            .unwrap()
+            .remove(0);
+        assert_eq!(frame.image[4][10], RGB8::new(0, 0, 0));
+        assert_eq!(frame.image[0][0], RGB8::new(14, 0, 0));
+    }
+    #[test]
+    fn mod_pow() {
+        assert_eq!(
+            pixel("N4N13N251I N2N10N1000I N7N0N13I").unwrap(),
+            RGB8::new(249, 24, 1)
+        );
+        // 2^1000 is far too big for a stack value
+        assert_eq!(pixel("N2N1000N255I N0N0").unwrap(), RGB8::new(1, 0, 0));
+        assert!(matches!(
+            pixel("N2N1000E"),
+            Err(FxytError::Runtime {
+                error: RuntimeError::IntegerOverflow,
+                ..
+            })
+        ));
+        // negative bases and moduli still give a result that's never negative
+        assert_eq!(
+            pixel("N3~N3N10I N3N3N10~I N5N0N1I").unwrap(),
+            RGB8::new(3, 7, 0)
+        );
+        assert_eq!(crate::mod_pow(2, 1000, 1_000_000_007), 688_423_210);
+        assert_eq!(
+            crate::mod_pow(isize::MAX, usize::MAX, isize::MIN),
+            isize::MAX
+        );
+
+        assert!(matches!(
+            pixel("N2N3N0I"),
+            Err(FxytError::Runtime {
+                error: RuntimeError::ModulusByZero,
+                ..
+            })
+        ));
+        assert_eq!(pixel("MMN2N3N0I").unwrap(), RGB8::new(255, 0, 0));
+        assert!(matches!(
+            pixel("N2N1~N5I"),
+            Err(FxytError::Runtime {
+                error: RuntimeError::NegativeExponent,
+                ..
+            })
+        ));
+    }
+    #[test]
+    fn grayscale() {
+        use crate::{render_with, ColourMode, RenderOptions};
+        let options = RenderOptions {
+            colours: ColourMode::Grayscale,
+            ..Default::default()
+        };
+        let frame = render_with("XY^", &options).unwrap().remove(0);
+        assert_eq!(frame.image[255][3], RGB8::new(3, 3, 3));
+        assert_eq!(frame.image[0][0], RGB8::new(255, 255, 255));
+
+        // anything but a lone value is read as usual
+        let frame = render_with("XY", &options).unwrap().remove(0);
+        assert_eq!(frame.image[255][3], RGB8::new(0, 3, 0));
+        let frame = render_with("", &options).unwrap().remove(0);
+        assert_eq!(frame.image[0][0], RGB8::new(0, 0, 0));
+
+        assert!(matches!(
+            render_with("N300", &options),
+            Err(FxytError::RgbOutOfRange {
+                red: 300,
+                green: 300,
+                blue: 300,
+                ..
+            })
+        ));
+        // off by default
+        assert_eq!(pixel("N7").unwrap(), RGB8::new(0, 0, 7));
+    }
+    #[test]
+    fn rgba_output() {
+        use crate::{render_rgba8, Program};
+        use rgb::RGBA8;
+        let frames = render_rgba8("XYN7N128").unwrap();
+        assert_eq!(frames[0].image[255][3], RGBA8::new(3, 0, 7, 128));
+        // three values or fewer get an opaque alpha
+        let frames = render_rgba8("XYN7").unwrap();
+        assert_eq!(frames[0].image[255][3], RGBA8::new(3, 0, 7, 255));
+        let frames = render_rgba8("X").unwrap();
+        assert_eq!(frames[0].image[255][3], RGBA8::new(0, 0, 3, 255));
+        // and RGB rendering ignores anything below the top three
+        assert_eq!(pixel("XYN7N128").unwrap(), RGB8::new(0, 7, 128));
+
+        let bytes = frames[0].to_rgba_bytes();
+        assert_eq!(bytes.data[..8], [0, 0, 0, 255, 0, 0, 1, 255]);
+
+        let error = Program::compile("N1N2N3N256")
+            .unwrap()
+            .render_rgba8()
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            FxytError::RgbOutOfRange {
+                red: 1,
+                green: 2,
+                blue: 3,
+                alpha: Some(256),
+                ..
+            }
+        ));
+        assert_eq!(
+            error.to_string(),
+            "RGBA value (1, 2, 3, 256) at (0, 0, 0) greater than 255 or less than 0"
+        );
+        assert!(matches!(
+            render_rgba8("N0N1-N2N3N4"),
+            Err(FxytError::RgbOutOfRange { red: -1, .. })
+        ));
+
+        // T in the fourth value from the top only shows up with alpha
+        let frames = render_rgba8("TN0N0N0").unwrap();
+        assert_eq!(frames.len(), 256);
+        assert_eq!(frames[200].image[0][0], RGBA8::new(200, 0, 0, 0));
+        assert_eq!(crate::render("TN0N0N0").unwrap().len(), 1);
+    }
+    #[test]
+    #[cfg(feature = "std")]
+    fn gamma() {
+        use crate::{render_rgba8, render_with, RenderOptions};
+        let options = RenderOptions {
+            gamma: Some(2.2),
+            ..Default::default()
+        };
+        let frame = render_with("N128DD", &options).unwrap().remove(0);
+        assert_eq!(frame.image[0][0], RGB8::new(186, 186, 186));
+        // the ends of the range stay put
+        let frame = render_with("N0N255N64", &options).unwrap().remove(0);
+        assert_eq!(frame.image[0][0], RGB8::new(0, 255, 136));
+
+        // off unless asked for
+        let frame = render_with("N128DD", &RenderOptions::default()).unwrap();
+        assert_eq!(frame[0].image[0][0], RGB8::new(128, 128, 128));
+        assert_eq!(render_rgba8("N128DDD").unwrap()[0].image[0][0].r, 128);
+    }
+    #[test]
+    #[cfg(feature = "gif")]
+    fn gif_round_trip() {
+        use crate::{render_sized, write_gif};
+        let mut frames: Vec<_> = render_sized("XYT^^N5F", 8, 4)
+            .unwrap()
+            .into_iter()
+            .take(3)
+            .collect();
+        frames[2].interval = 30;
+        let mut encoded = Vec::new();
+        write_gif(&frames, &mut encoded).unwrap();
+        assert_eq!(encoded[..6], *b"GIF89a");
+
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::RGBA);
+        let mut decoder = options.read_info(encoded.as_slice()).unwrap();
+        assert_eq!((decoder.width(), decoder.height()), (8, 4));
+        let mut delays = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            // the top left pixel is 3 ^ t, give or take a little from quantizing
+            let expected = 3 ^ delays.len() as u8;
+            assert!(frame.buffer[2].abs_diff(expected) <= 1);
+            delays.push(frame.delay);
+        }
+        assert_eq!(delays, [5, 5, 30]);
+    }
+    #[test]
+    #[cfg(feature = "gif")]
+    fn gif_from_fps() {
+        use crate::{render_sized, write_gif_with, GifOptions};
+        assert_eq!(GifOptions::from_fps(25).delay, Some(4));
+        assert_eq!(GifOptions::from_fps(30).delay, Some(3));
+        assert_eq!(GifOptions::from_fps(1).delay, Some(100));
+        assert_eq!(GifOptions::from_fps(1000).delay, Some(1));
+        assert_eq!(GifOptions::from_fps(u32::MAX).delay, Some(1));
+        assert_eq!(GifOptions::from_fps(0).delay, None);
+
+        // the middle frame sets its own interval, which wins
+        let frames: Vec<_> = render_sized("XYT^^ TN1=?[N9F]", 4, 4)
+            .unwrap()
+            .into_iter()
+            .take(3)
+            .collect();
+        assert!(!frames[0].custom_interval && frames[1].custom_interval);
+        let mut encoded = Vec::new();
+        write_gif_with(&frames, &mut encoded, &GifOptions::from_fps(25)).unwrap();
+        let mut decoder = gif::DecodeOptions::new()
+            .read_info(encoded.as_slice())
+            .unwrap();
+        let mut delays = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            delays.push(frame.delay);
+        }
+        assert_eq!(delays, [4, 9, 4]);
+    }
+    #[test]
+    #[cfg(feature = "gif")]
+    fn gif_global_palette() {
+        use crate::{render_sized, write_gif_with, GifOptions};
+        // a gradient that slides along with T
+        let frames: Vec<_> = render_sized("XT+N256%N0N0", 16, 2)
+            .unwrap()
+            .into_iter()
+            .take(4)
+            .collect();
+        let options = GifOptions {
+            global_palette: true,
+            ..Default::default()
+        };
+        let mut encoded = Vec::new();
+        write_gif_with(&frames, &mut encoded, &options).unwrap();
+
+        let mut decode = gif::DecodeOptions::new();
+        decode.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = decode.clone().read_info(encoded.as_slice()).unwrap();
+        let palette = decoder.global_palette().unwrap().to_vec();
+        let mut count = 0;
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            assert!(frame.palette.is_none());
+            for (x, &index) in frame.buffer[..16].iter().enumerate() {
+                let red = palette[index as usize * 3];
+                assert_eq!(red, (x + count) as u8);
+            }
+            count += 1;
+        }
+        assert_eq!(count, 4);
+        // too many colours to keep them all
+        let frames = render_sized("XYN0", 32, 32).unwrap();
+        write_gif_with(&frames, &mut Vec::new(), &options).unwrap();
+
+        // and without it, every frame brings its own
+        let mut encoded = Vec::new();
+        crate::write_gif(&frames, &mut encoded).unwrap();
+        let mut decoder = decode.read_info(encoded.as_slice()).unwrap();
+        assert!(decoder
+            .read_next_frame()
+            .unwrap()
+            .unwrap()
+            .palette
+            .is_some());
+    }
+    #[test]
+    #[cfg(feature = "gif")]
+    fn gif_dither() {
+        use crate::{render_sized, write_gif_with, GifOptions};
+        let decode = |encoded: &[u8]| {
+            let mut options = gif::DecodeOptions::new();
+            options.set_color_output(gif::ColorOutput::RGBA);
+            let mut decoder = options.read_info(encoded).unwrap();
+            decoder.read_next_frame().unwrap().unwrap().buffer.to_vec()
+        };
+        // a smooth grey ramp, few enough colours to come through exactly without dithering
+        let frames = render_sized("XN8*DD", 32, 8).unwrap();
+        let mut plain = Vec::new();
+        write_gif_with(&frames, &mut plain, &GifOptions::default()).unwrap();
+        let options = GifOptions {
+            dither: true,
+            ..Default::default()
+        };
+        let mut dithered = Vec::new();
+        write_gif_with(&frames, &mut dithered, &options).unwrap();
+
+        let (plain, dithered_pixels) = (decode(&plain), decode(&dithered));
+        assert_eq!(plain[4 * 5], 40);
+        let changed = plain
+            .iter()
+            .zip(&dithered_pixels)
+            .filter(|(a, b)| a != b)
+            .count();
+        assert!(changed > plain.len() / 2);
+
+        // the same every time
+        let mut again = Vec::new();
+        write_gif_with(&frames, &mut again, &options).unwrap();
+        assert_eq!(dithered, again);
+    }
+    #[test]
+    fn progress() {
+        use crate::render_with_progress;
+        let mut calls = Vec::new();
+        let frames =
+            render_with_progress("XYT^^", &mut |done, total| calls.push((done, total))).unwrap();
+        assert_eq!(calls.len(), frames.len());
+        assert!(calls.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert_eq!(calls.last(), Some(&(256, 256)));
+
+        calls.clear();
+        render_with_progress("XY^", &mut |done, total| calls.push((done, total))).unwrap();
+        assert_eq!(calls, [(1, 1)]);
+        // nothing more once something goes wrong
+        calls.clear();
+        assert!(
+            render_with_progress("N1N0/", &mut |done, total| calls.push((done, total))).is_err()
+        );
+        assert!(calls.is_empty());
+    }
+    #[test]
+    fn error_positions() {
+        use crate::{render_sized, Arithmetic, Program, RenderOptions};
+        let pos = |program: &str| match pixel(program) {
+            Err(FxytError::Runtime { pos, .. }) => pos,
+            result => panic!("{result:?}"),
+        };
+        assert_eq!(pos("XY^ N1N0/"), Some(8));
+        // inside a loop inside a loop
+        assert_eq!(pos("N2[N1[P]]"), Some(6));
+        // the loop itself, and commands after loops
+        assert_eq!(pos("[XY^]"), Some(0));
+        assert_eq!(pos("N1[N2[XP]]N0N1-Q"), Some(15));
+        assert_eq!(pos("N1L[N1]"), Some(2));
+        assert_eq!(pos("XY<= D N5 ?[ N1N0/ ]"), Some(17));
+
+        // optimizing keeps the positions, with folded pushes where their first command was
+        let mut program = Program::compile("N2N3*XN1N0/").unwrap();
+        program.optimize(&RenderOptions::default());
+        assert_eq!(
+            program.commands,
+            vec![
+                Literal(6),
+                Coordinates(crate::Coordinates::X),
+                Literal(1),
+                Literal(0),
+                Arithmetic(Arithmetic::Divide)
+            ]
+        );
+        assert_eq!(program.positions, [0, 5, 6, 8, 10]);
+        assert!(matches!(
+            program.render_pixel(0, 0, 0),
+            Err(FxytError::Runtime { pos: Some(10), .. })
+        ));
+
+        let error = render_sized("N3X-QN0N0", 5, 1).unwrap_err();
+        assert!(matches!(
+            error,
+            FxytError::Runtime {
+                error: RuntimeError::NegativeSqrt,
+                coords: Coords { x: 4, y: 0, t: 0 },
+                pos: Some(4),
+            }
+        ));
+        assert_eq!(
+            error.to_string(),
+            "Attempt to take the square root of a negative number at (4, 0, 0), from the \
+             command at position `4`"
+        );
+    }
+    #[test]
+    fn op_budget() {
+        use crate::{render_with, RenderOptions};
+        let options = |max_ops| RenderOptions {
+            max_ops: Some(max_ops),
+            ..Default::default()
+        };
+        // exactly enough
+        assert!(render_with("XY^", &options(3)).is_ok());
+        assert!(matches!(
+            render_with("XY^", &options(2)),
+            Err(FxytError::Runtime {
+                error: RuntimeError::OpBudgetExceeded,
+                ..
+            })
+        ));
+        // every pass through a loop counts, as well as what's in it
+        assert!(render_with("N5[XP]XY^", &options(21)).is_ok());
+        assert!(matches!(
+            render_with("N5[XP]XY^", &options(20)),
+            Err(FxytError::Runtime {
+                error: RuntimeError::OpBudgetExceeded,
+                ..
+            })
+        ));
+        assert!(matches!(
+            render_with("N99[N99[N99[N99[]]]]XY^", &options(100_000)),
+            Err(FxytError::Runtime {
+                error: RuntimeError::OpBudgetExceeded,
+                ..
+            })
+        ));
+        // even when there's nothing in it
+        assert!(matches!(
+            render_with("N99999999999999[]", &options(1000)),
+            Err(FxytError::Runtime {
+                error: RuntimeError::OpBudgetExceeded,
+                ..
+            })
+        ));
+    }
+    #[test]
+    fn implicit_integer() {
+        let parsed = |program: &[u8]| parse(program, 0, 0, &mut Vec::new()).unwrap().1;
+        // nothing to append to, so these start fresh
+        assert_eq!(
+            parsed(b"12X"),
+            [
+                Integer,
+                Digit(1),
+                Digit(2),
+                Coordinates(crate::Coordinates::X)
+            ]
+        );
+        assert_eq!(
+            parsed(b"XZ 5"),
+            [Coordinates(crate::Coordinates::X), Clear, Integer, Digit(5)]
+        );
+        assert_eq!(pixel("255 N0N0").unwrap(), RGB8::new(255, 0, 0));
+        // and the same once everything has been popped, wherever that is
+        assert_eq!(
+            parsed(b"XP5"),
+            [
+                Coordinates(crate::Coordinates::X),
+                StackOperation(crate::StackOperation::Pop),
+                Integer,
+                Digit(5)
+            ]
+        );
+        assert_eq!(pixel("XP5DD").unwrap(), RGB8::new(5, 5, 5));
+        // but after a value they still append
+        assert_eq!(
+            parsed(b"X5"),
+            [Coordinates(crate::Coordinates::X), Digit(5)]
+        );
+        assert_eq!(parsed(b"N1 2"), [Integer, Digit(1), Digit(2)]);
+        assert_eq!(pixel("12 3 N0N0").unwrap(), RGB8::new(123, 0, 0));
+        assert_eq!(
+            crate::render_pixel("XYP5 N0N0", 7, 0, 0).unwrap(),
+            RGB8::new(75, 0, 0)
+        );
+        // and in loop bodies, or after one that changes the stack, nobody knows what's there
+        assert_eq!(parsed(b"N1[5]"), [Integer, Digit(1), Loop(vec![Digit(5)])]);
+        assert_eq!(
+            parsed(b"N2[P]5"),
+            [
+                Integer,
+                Digit(2),
+                Loop(vec![StackOperation(crate::StackOperation::Pop)]),
+                Digit(5)
+            ]
+        );
+        // the implicit N is reported where the digits start
+        let mut positions = Vec::new();
+        parse(b" 42", 0, 0, &mut positions).unwrap();
+        assert_eq!(positions, [1, 1, 2]);
+    }
+    #[test]
+    fn hex_literals() {
+        let parsed = |program: &[u8]| parse(program, 0, 0, &mut Vec::new());
+        assert_eq!(parsed(b"$0").unwrap().1, [Integer, Digit(0)]);
+        assert_eq!(
+            parsed(b"$FF").unwrap().1,
+            [Integer, Digit(2), Digit(5), Digit(5)]
+        );
+        assert_eq!(
+            parsed(b"$100").unwrap().1,
+            [Integer, Digit(2), Digit(5), Digit(6)]
+        );
+        assert_eq!(pixel("$FF $0 $7F").unwrap(), RGB8::new(255, 0, 127));
+        // hex digits are taken greedily, even ones that are also commands
+        assert_eq!(pixel("$FD $FF D").unwrap(), RGB8::new(253, 255, 255));
+        assert_eq!(
+            parsed(b"$7FFFFFFFFFFFFFFF").unwrap().1.len(),
+            isize::MAX.to_string().len() + 1
+        );
+
+        assert_eq!(
+            parsed(b"$G").unwrap_err(),
+            ParseError::InvalidCharacter(0..2)
+        );
+        // only uppercase, so a lowercase command after an uppercase digit isn't swallowed
+        assert_eq!(
+            parsed(b"$ff").unwrap_err(),
+            ParseError::InvalidCharacter(0..2)
+        );
+        assert_eq!(
+            parsed(b"$Fd").unwrap().1,
+            [
+                Integer,
+                Digit(1),
+                Digit(5),
+                StackOperation(crate::StackOperation::Duplicate)
+            ]
+        );
+        assert_eq!(
+            parsed(b"N1$").unwrap_err(),
+            ParseError::InvalidCharacter(2..3)
+        );
+        assert!(matches!(
+            parsed(b"X $8000000000000000"),
+            Err(ParseError::LiteralTooLarge(2))
+        ));
+        // the pushes are reported where the literal starts
+        let mut positions = Vec::new();
+        parse(b"X $1F", 0, 0, &mut positions).unwrap();
+        assert_eq!(positions, [0, 2, 2, 2]);
+    }
+    #[test]
+    fn min_max() {
+        assert_eq!(pixel("N3N7{N3N7}N7N3{").unwrap(), RGB8::new(3, 7, 3));
+        assert_eq!(pixel("N0N5-N2{N0S-N0N0").unwrap(), RGB8::new(5, 0, 0));
+        // gradient clamped into a band
+        assert_eq!(
+            crate::render_pixel("XN64}N192{DD", 10, 0, 0).unwrap(),
+            RGB8::new(64, 64, 64)
+        );
+        assert_eq!(
+            crate::render_pixel("XN64}N192{DD", 100, 0, 0).unwrap(),
+            RGB8::new(100, 100, 100)
+        );
+        assert!(matches!(
+            pixel("N1{"),
+            Err(FxytError::Runtime {
+                error: RuntimeError::StackEmpty,
+                ..
+            })
+        ));
+        assert_eq!(
+            parse(b"{}", 0, 0, &mut Vec::new()).unwrap(),
+            (2, vec![Command::Min, Command::Max])
+        );
+    }
+    /// Walks the chunks of a PNG looking for one with the given type, and returns its data.
+    #[cfg(feature = "apng")]
+    fn png_chunk<'a>(png: &'a [u8], chunk_type: &[u8; 4]) -> Option<&'a [u8]> {
+        let mut offset = 8;
+        while offset < png.len() {
+            let length = u32::from_be_bytes(png[offset..offset + 4].try_into().unwrap()) as usize;
+            let data = offset + 8;
+            if &png[offset + 4..data] == chunk_type {
+                return Some(&png[data..data + length]);
+            }
+            offset = data + length + 4;
+        }
+        None
+    }
+
+    #[test]
+    #[cfg(feature = "apng")]
+    fn apng_signature_and_frame_count() {
+        use crate::{render_apng, RenderOptions};
+        let apng = render_apng("XT^N0N0", &RenderOptions::default()).unwrap();
+        assert_eq!(apng[..8], *b"\x89PNG\r\n\x1a\n");
 
-#[cfg(test)]
-mod test {
-    use crate::{parse, Command};
-    use rgb::{ComponentSlice, RGB8};
-    use std::fs::File;
-    use std::io::Write;
-    use Command::*;
+        let frame_count = png_chunk(&apng, b"acTL").unwrap();
+        let frame_count = u32::from_be_bytes(frame_count[..4].try_into().unwrap());
+        assert_eq!(frame_count, 256);
+    }
+    #[test]
+    fn spiral_angle_and_radius() {
+        let at = |x, y| Coords::new(x, y, 0);
+        assert_eq!((at(228, 128).angle(), at(228, 128).radius()), (0, 100));
+        assert_eq!((at(128, 228).angle(), at(128, 228).radius()), (64, 100));
+        assert_eq!((at(28, 128).angle(), at(28, 128).radius()), (128, 100));
+        assert_eq!((at(128, 28).angle(), at(128, 28).radius()), (192, 100));
+        assert_eq!(at(200, 200).angle(), 32);
+        assert_eq!(at(56, 56).angle(), 160);
+    }
+    #[test]
+    fn spiral_twist_adds_arms() {
+        use crate::render_row;
+        // count how many bands a ray from the centre towards +X crosses
+        fn arms_crossed(twist: usize) -> usize {
+            let row = render_row(&format!("N{twist}VDD"), 128, 0).unwrap();
+            row[128..].windows(2).filter(|w| w[1].r < w[0].r).count()
+        }
 
+        assert!(arms_crossed(2) < arms_crossed(4));
+        assert!(arms_crossed(4) < arms_crossed(16));
+    }
     #[test]
-    #[ignore = "file i/o"]
-    fn manual_render_check() {
+    fn hash_is_deterministic() {
+        let at = |x, y, t| Coords::new(x, y, t);
+        assert_eq!(at(10, 20, 3).hash(7), at(10, 20, 3).hash(7));
+        assert_ne!(at(10, 20, 3).hash(7), at(11, 20, 3).hash(7));
+        assert_ne!(at(10, 20, 3).hash(7), at(10, 20, 3).hash(8));
+        assert!((0..256).all(|x| (0..256).contains(&at(x, 0, 0).hash(-1))));
+
+        let value = |x, y, t| crate::render_pixel("N5HDD", x, y, t).unwrap().r;
+        assert_eq!(value(10, 20, 3), value(10, 20, 3));
+        let row: Vec<_> = (0..16).map(|x| value(x, 20, 3)).collect();
+        assert!(row.windows(2).any(|w| w[0] != w[1]));
+        assert!(crate::Program::compile("N1HDD").unwrap().is_animated());
+        assert!(matches!(
+            pixel("H"),
+            Err(FxytError::Runtime {
+                error: RuntimeError::StackEmpty,
+                ..
+            })
+        ));
+    }
+    #[test]
+    fn registers() {
+        assert_eq!(pixel("N7:0 ;0;0;0").unwrap(), RGB8::new(7, 7, 7));
+        // overwriting, and an empty register reads as 0
+        assert_eq!(pixel("N1:1 N2:1 ;1;2;1").unwrap(), RGB8::new(2, 0, 2));
+        // registers don't take up stack space, so the stack can still fill up beside them
+        assert_eq!(
+            pixel("N1:0N2:1N3:2N4:3 N9N9N9N9 ;3;2;1;0 Z;3;2;1").unwrap(),
+            RGB8::new(4, 3, 2)
+        );
+        // they start afresh for every pixel
+        assert_eq!(
+            crate::render_row(";0 X+D:0 DD", 0, 0).unwrap()[..3],
+            [RGB8::new(0, 0, 0), RGB8::new(1, 1, 1), RGB8::new(2, 2, 2)]
+        );
+        assert!(matches!(
+            pixel(":0"),
+            Err(FxytError::Runtime {
+                error: RuntimeError::StackEmpty,
+                ..
+            })
+        ));
+        assert_eq!(
+            parse(b"N1:4", 0, 0, &mut Vec::new()).unwrap_err(),
+            ParseError::InvalidCharacter(2..4)
+        );
+        assert_eq!(
+            parse(b"N1;", 0, 0, &mut Vec::new()).unwrap_err(),
+            ParseError::InvalidCharacter(2..3)
+        );
+        assert!(crate::Program::compile("T:0 ;0DD").unwrap().is_animated());
+        assert!(!crate::Program::compile("X:0 ;0DD").unwrap().is_animated());
+
+        // the parser won't produce these, but they can be put together by hand
+        for command in [Store(200), Load(4)] {
+            let program = crate::Program {
+                commands: vec![Integer, Digit(1), command, Integer, Integer, Integer],
+                positions: Vec::new(),
+            };
+            assert!(matches!(
+                program.render_pixel(0, 0, 0),
+                Err(FxytError::Runtime {
+                    error: RuntimeError::RegisterOutOfRange,
+                    ..
+                })
+            ));
+        }
+    }
+    #[test]
+    fn gcd() {
+        assert_eq!(pixel("N12 N18G N7 N5G N0 N9G").unwrap(), RGB8::new(6, 1, 9));
+        assert_eq!(pixel("N9 N0G N0 N0G N1 N1G").unwrap(), RGB8::new(9, 0, 1));
+        // signs are ignored
+        assert_eq!(
+            pixel("N12~ N8G N12 N8~G N12~ N8~G").unwrap(),
+            RGB8::new(4, 4, 4)
+        );
+        assert_eq!(crate::gcd(isize::MIN, 6), Some(2));
+        assert_eq!(crate::gcd(isize::MIN, 0), None);
+        assert!(matches!(
+            pixel("N1G"),
+            Err(FxytError::Runtime {
+                error: RuntimeError::StackEmpty,
+                ..
+            })
+        ));
+    }
+    #[test]
+    fn clamp() {
+        // in range, below the lower bound and above the upper bound
+        assert_eq!(
+            pixel("N50 N10N90K N5 N10N90K N95 N10N90K").unwrap(),
+            RGB8::new(50, 10, 90)
+        );
+        // bounds that are equal pin everything to them
+        assert_eq!(
+            pixel("N3 N7N7K N9 N7N7K N7 N7N7K").unwrap(),
+            RGB8::new(7, 7, 7)
+        );
+        assert!(matches!(
+            pixel("N50 N90N10K"),
+            Err(FxytError::Runtime {
+                error: RuntimeError::ClampBoundsInverted,
+                ..
+            })
+        ));
+        assert!(matches!(
+            pixel("N1N2K"),
+            Err(FxytError::Runtime {
+                error: RuntimeError::StackEmpty,
+                ..
+            })
+        ));
+    }
+    #[test]
+    fn swap_deep() {
+        assert_eq!(pixel("N1N2N3 N1J").unwrap(), pixel("N1N2N3 S").unwrap());
+        assert_eq!(pixel("N1N2N3 N2J").unwrap(), RGB8::new(3, 2, 1));
+        assert_eq!(pixel("N1N2N3 N0J").unwrap(), RGB8::new(1, 2, 3));
+        assert_eq!(pixel("N1N2N3N4N5N6 N5J PPP").unwrap(), RGB8::new(6, 2, 3));
+        for out_of_range in ["N1N2N3 N3J", "N1N2N3 N1~J", "N0J"] {
+            assert!(matches!(
+                pixel(out_of_range),
+                Err(FxytError::Runtime {
+                    error: RuntimeError::StackEmpty,
+                    ..
+                })
+            ));
+        }
+        assert!(crate::Program::compile("XYT N2J").unwrap().is_animated());
+        assert!(crate::Program::compile("XYN1 TJ").unwrap().is_animated());
+    }
+    #[test]
+    fn roll() {
+        assert_eq!(pixel("N1N2N3 N0`").unwrap(), RGB8::new(1, 2, 3));
+        assert_eq!(pixel("N1N2N3 N1`").unwrap(), pixel("N1N2N3 S").unwrap());
+        assert_eq!(pixel("N1N2N3 N2`").unwrap(), pixel("N1N2N3 R").unwrap());
+        assert_eq!(pixel("N1N2N3N4N5N6 N5` PPP").unwrap(), RGB8::new(2, 3, 4));
+        assert_eq!(
+            pixel("N1N2N3N4N5N6 N5` RRR N4`PPP").unwrap(),
+            RGB8::new(2, 4, 5)
+        );
+        for out_of_range in ["N1N2N3 N3`", "N1N2N3 N1~`", "N0`"] {
+            assert!(matches!(
+                pixel(out_of_range),
+                Err(FxytError::Runtime {
+                    error: RuntimeError::StackEmpty,
+                    ..
+                })
+            ));
+        }
+        assert!(crate::Program::compile("TXY N2`").unwrap().is_animated());
+        assert!(crate::Program::compile("XYN1 T`").unwrap().is_animated());
+    }
+    #[test]
+    fn rgb_out_of_range_reports_pixel() {
         use crate::render;
-        let output = render("XY^").unwrap();
-        write_ppm(output[0].image);
+        // only the pixel at (200, 17) goes out of range
+        let error = render("XN200=YN17=&N300*N0N0").unwrap_err();
+        assert!(matches!(
+            error,
+            FxytError::RgbOutOfRange {
+                coords: Coords {
+                    x: 200,
+                    y: 17,
+                    t: 0
+                },
+                red: 300,
+                green: 0,
+                blue: 0,
+                alpha: None,
+            }
+        ));
+        assert_eq!(
+            error.to_string(),
+            "RGB value (300, 0, 0) at (200, 17, 0) greater than 255 or less than 0"
+        );
+    }
+    #[test]
+    fn frame_interval_sets_frame() {
+        use crate::render;
+        assert_eq!(render("XY^DDN5F").unwrap()[0].interval, 5);
+        assert_eq!(render("XY^DD").unwrap()[0].interval, 100);
+    }
+    #[test]
+    fn frame_interval_out_of_range_errors() {
+        assert!(matches!(
+            pixel("N0N5-F"),
+            Err(FxytError::Runtime {
+                error: RuntimeError::FrameIntervalOutOfRange,
+                ..
+            })
+        ));
+    }
+    #[test]
+    fn modulus_by_zero_follows_mode() {
+        assert!(matches!(
+            pixel("N5N0%"),
+            Err(FxytError::Runtime {
+                error: RuntimeError::ModulusByZero,
+                ..
+            })
+        ));
+        assert!(matches!(
+            pixel("N5N0/"),
+            Err(FxytError::Runtime {
+                error: RuntimeError::DivideByZero,
+                ..
+            })
+        ));
+        assert_eq!(pixel("MN5N0%").unwrap(), RGB8::new(0, 0, 0));
+        assert_eq!(pixel("MMN5N0%").unwrap(), RGB8::new(255, 0, 0));
+        assert_eq!(pixel("N5N3%N0N0").unwrap(), RGB8::new(2, 0, 0));
+    }
+    #[test]
+    fn unclosed_bracket() {
+        assert!(matches!(
+            parse(b"N5[N10+", 0, 0, &mut Vec::new()),
+            Err(ParseError::BracketMismatch(2))
+        ));
+        assert!(matches!(
+            parse(b"[[]", 0, 0, &mut Vec::new()),
+            Err(ParseError::BracketMismatch(0))
+        ));
+    }
+    #[test]
+    fn unclosed_nested_bracket() {
+        assert!(matches!(
+            parse(b"N5[N10[N4+", 0, 0, &mut Vec::new()),
+            Err(ParseError::BracketMismatch(6))
+        ));
+        assert!(matches!(
+            parse(b"N5[N10[N4+]", 0, 0, &mut Vec::new()),
+            Err(ParseError::BracketMismatch(2))
+        ));
+    }
+    #[test]
+    fn closed_loop_consumed_length() {
+        assert_eq!(parse(b"N5[N1+]N2", 0, 0, &mut Vec::new()).unwrap().0, 9);
+    }
+    #[test]
+    fn arithmetic_overflow_errors() {
+        let max = isize::MAX;
+        let overflows = |program: String| {
+            matches!(
+                pixel(&program),
+                Err(FxytError::Runtime {
+                    error: RuntimeError::IntegerOverflow,
+                    ..
+                })
+            )
+        };
+        assert!(overflows(format!("N{max}N1+")));
+        assert!(overflows(format!("N0N{max}-N2-")));
+        assert!(overflows("N4294967296D*".to_string()));
+        assert!(overflows(format!("N0N{max}-N1-N0N1-/")));
+        assert!(overflows(format!("N0N{max}-N1-N0N1-%")));
+        assert!(overflows(format!("N0N{max}N1+_")));
+        // one past isize::MAX, via digit appending
+        assert!(overflows(format!("N{}", max as usize + 1)));
+        assert!(pixel(&format!("N{max}PN0N0N0")).is_ok());
+    }
+    #[test]
+    fn frame_interval_from_last_pixel() {
+        use crate::render;
+        // every pixel sets its own interval, the last one rendered (x = 255) wins
+        assert_eq!(render("N0N0N0XF").unwrap()[0].interval, 255);
     }
+    #[test]
+    fn render_sized_follows_dimensions() {
+        use crate::{render, render_sized};
+        let program = "XY^N255%N0N0";
+        let full = render(program).unwrap().remove(0);
+        assert_eq!((full.width(), full.height()), (256, 256));
+
+        let small = render_sized(program, 64, 32).unwrap().remove(0);
+        assert_eq!((small.width(), small.height()), (64, 32));
+        // the small canvas is the bottom left corner of the full one
+        for (row, full_row) in small.image.iter().zip(&full.image[256 - 32..]) {
+            assert_eq!(row[..], full_row[..64]);
+        }
+
+        let large = render_sized(program, 300, 260).unwrap().remove(0);
+        assert_eq!((large.width(), large.height()), (300, 260));
+        assert_eq!(large.image[0][299], RGB8::new((299usize ^ 259) as u8, 0, 0));
+    }
+    #[test]
+    fn custom_frame_range() {
+        use crate::{render_with, RenderOptions};
+        let options = RenderOptions {
+            frames: Some(60),
+            t_start: 10,
+            ..Default::default()
+        };
+        let frames = render_with("TN0N0", &options).unwrap();
+        assert_eq!(frames.len(), 60);
+        assert_eq!(frames[0].image[0][0].r, 10);
+        assert_eq!(frames[59].image[0][0].r, 69);
+
+        // still programs ignore the frame count
+        assert_eq!(render_with("XN0N0", &options).unwrap().len(), 1);
 
-    fn write_ppm(image_data: [[RGB8; 256]; 256]) {
-        let mut file = File::create("output.ppm").unwrap();
+        // past the largest T, it wraps around rather than overflowing
+        let options = RenderOptions::new().frames(2).t_start(usize::MAX);
+        let frames = render_with("TN255&", &options).unwrap();
+        assert_eq!(frames[0].image[0][0], RGB8::new(0, 0, 255));
+        assert_eq!(frames[1].image[0][0], RGB8::new(0, 0, 0));
+    }
+    #[test]
+    fn seamless_custom_frame_range() {
+        use crate::{render_with, RenderOptions};
+        let options = RenderOptions {
+            seamless: true,
+            frames: Some(5),
+            t_start: 100,
+            ..Default::default()
+        };
+        let ts: Vec<_> = render_with("TN0N0", &options)
+            .unwrap()
+            .iter()
+            .map(|frame| frame.image[0][0].r)
+            .collect();
+        assert_eq!(ts, [100, 102, 104, 103, 101]);
+    }
+    #[test]
+    fn render_frame_matches_render() {
+        use crate::{render, render_frame, render_with, RenderOptions};
+        let program = "XYT+^N256%DD";
+        let options = RenderOptions {
+            frames: Some(6),
+            ..Default::default()
+        };
+        let frames = render_with(program, &options).unwrap();
+        assert_eq!(render_frame(program, 5).unwrap(), frames[5]);
 
-        writeln!(file, "P6\n256 256\n255").unwrap();
+        let still = "XY|N0N0";
+        assert_eq!(render_frame(still, 5).unwrap(), render(still).unwrap()[0]);
+    }
+    #[test]
+    fn validate() {
+        use crate::{validate, ProgramInfo};
+        assert_eq!(
+            validate("XY^ N5F").unwrap(),
+            ProgramInfo {
+                animated: false,
+                max_nesting: 0,
+                command_count: 6,
+                has_debug: false,
+                has_frame_interval: true,
+                warnings: vec![],
+            }
+        );
+        assert_eq!(
+            validate("XYT^^ N2[N1?[W]] L[N0]").unwrap(),
+            ProgramInfo {
+                animated: true,
+                max_nesting: 2,
+                command_count: 15,
+                has_debug: true,
+                has_frame_interval: false,
+                warnings: vec![],
+            }
+        );
+        // only parsing, so it doesn't notice this would fail every pixel
+        assert_eq!(validate("P").unwrap().command_count, 1);
+        assert_eq!(
+            validate("XY^]").unwrap_err(),
+            FxytError::Parse(ParseError::InvalidCharacter(3..4))
+        );
+    }
+    #[test]
+    fn program_iter() {
+        use crate::Program;
+        let program = Program::compile("N2[X N3[Y] ?[Z]] T").unwrap();
+        let walked: Vec<_> = program
+            .iter()
+            .map(|(command, depth)| (command.kind(), depth))
+            .collect();
+        let expected = [
+            ("N", 0),
+            ("2", 0),
+            ("[", 0),
+            ("X", 1),
+            ("N", 1),
+            ("3", 1),
+            ("[", 1),
+            ("Y", 2),
+            ("?[", 1),
+            ("Z", 2),
+            ("T", 0),
+        ];
+        let expected: Vec<_> = expected
+            .iter()
+            .map(|&(kind, depth)| (String::from(kind), depth))
+            .collect();
+        assert_eq!(walked, expected);
+        assert_eq!(Program::compile("").unwrap().iter().count(), 0);
+        assert_eq!(
+            Program::compile("[[[]]]").unwrap().iter().last(),
+            Some((&Loop(vec![]), 2))
+        );
+    }
+    #[test]
+    fn program_visit_mut() {
+        use crate::Program;
+        let mut program = Program::compile("N1[N2?[N3L[N4]]]").unwrap();
+        let mut visited = 0;
+        program
+            .visit_mut(|command| {
+                visited += 1;
+                if let Digit(digit) = command {
+                    *digit += 5;
+                }
+            })
+            .unwrap();
+        assert_eq!(visited, program.iter().count());
+        assert_eq!(program, Program::compile("N6[N7?[N8L[N9]]]").unwrap());
+        // same shape, so errors still point into the source
+        let mut program = Program::compile("N1 [N0 N0/]").unwrap();
+        program
+            .visit_mut(|command| {
+                if let Integer = command {
+                    *command = Literal(0);
+                }
+            })
+            .unwrap();
+        assert_eq!(
+            program.render().unwrap_err(),
+            FxytError::Runtime {
+                error: RuntimeError::DivideByZero,
+                pos: Some(9),
+                coords: Coords { x: 0, y: 0, t: 0 },
+            }
+        );
+        // a body that changes length leaves nothing to point at
+        program
+            .visit_mut(|command| {
+                if let Loop(inner_commands) = command {
+                    inner_commands.insert(0, Integer);
+                }
+            })
+            .unwrap();
+        assert!(matches!(
+            program.render().unwrap_err(),
+            FxytError::Runtime { pos: None, .. }
+        ));
 
-        for row in image_data {
-            for pixel in row {
-                file.write_all(pixel.as_slice()).unwrap();
-                // write!(file, "{}{}{}", pixel.r, pixel.g, pixel.b).unwrap()
+        // rewrites the parser would never allow fail rather than panic
+        let mut program = Program::compile("N1:0 ;0N0N0").unwrap();
+        program
+            .visit_mut(|command| {
+                if let Store(register) | Load(register) = command {
+                    *register = 200;
+                }
+            })
+            .unwrap();
+        assert!(matches!(
+            program.render().unwrap_err(),
+            FxytError::Runtime {
+                error: RuntimeError::RegisterOutOfRange,
+                ..
             }
+        ));
+        let mut program = Program::compile("N1[N1[N1[N1[N1[N1[N1[N1[X]]]]]]]] DD").unwrap();
+        let unchanged = program.clone();
+        assert_eq!(
+            program.visit_mut(|command| {
+                if let Coordinates(_) = command {
+                    *command = Loop(vec![Integer]);
+                }
+            }),
+            Err(ParseError::LoopNesting)
+        );
+        assert_eq!(program, unchanged);
+    }
+    #[test]
+    fn describe_error() {
+        use crate::{describe_error, render};
+        assert_eq!(
+            describe_error(&render("XY]").unwrap_err()),
+            "Failed to parse command: Found character that is not a valid FXYT command at \
+             position `2`"
+        );
+        assert_eq!(
+            describe_error(&RuntimeError::StackEmpty),
+            "Attempt to read from an empty stack"
+        );
+    }
+    #[test]
+    fn unreachable_warnings() {
+        use crate::{validate, Program, RenderOptions, Warning};
+        let warnings = |program| validate(program).unwrap().warnings;
+        let at = |pos| Warning::Unreachable { pos: Some(pos) };
+        assert_eq!(warnings("XYW DD"), [at(4)]);
+        assert_eq!(warnings("XY N0/ DD"), [at(7)]);
+        assert_eq!(warnings("XY N00% N2[D]"), [at(8)]);
+        // only the first of a run is reported, and loop bodies are checked separately
+        assert_eq!(warnings("N1[W XW Y] W N2[X]"), [at(5), at(13)]);
+        assert_eq!(warnings("N1?[N0/X]").len(), 1);
+
+        // nothing to report
+        for program in [
+            "XY^",
+            "XYDW",
+            "N1?[W] XYD",
+            "XY N10/ D",
+            "XY N0P/ D",
+            "N1[W]",
+            "N0N5/DD",
+        ] {
+            assert_eq!(warnings(program), [], "{program}");
         }
+
+        // folding doesn't hide a zero
+        let mut program = Program::compile("XY N0N0* / D").unwrap();
+        program.optimize(&RenderOptions::default());
+        assert_eq!(program.info().warnings, [at(11)]);
+        assert_eq!(
+            at(4).to_string(),
+            "Found commands after one that always stops the program, from the command at \
+             position `4`"
+        );
     }
+    #[test]
+    fn format() {
+        use crate::format;
+        assert_eq!(
+            format("N5[XN10+ N2?[D] # comment\n]YY*[]").unwrap(),
+            "N5[\n    XN10+N2?[\n        D\n    ]\n]\nYY*[]\n"
+        );
+        assert_eq!(format("XY< =N0[> =]").unwrap(), "XY< =N0[\n    > =\n]\n");
+        assert_eq!(format("[[X]]").unwrap(), "[\n    [\n        X\n    ]\n]\n");
+        assert_eq!(format(" # nothing here").unwrap(), "");
 
+        let corpus = [
+            "XY^",
+            "N0N1N5[D_N1+[XY^]]PN0N0",
+            "TN2/D*N256%XY&MN1N0/!<>=|CDPSRFW",
+            "N16VBU{}()AQE~@ZO?[N1?[]]L[N0]<=>= # all the newer commands\n",
+            "L[L[N0]]N1[]X",
+        ];
+        for source in corpus {
+            let formatted = format(source).unwrap();
+            assert_eq!(format(&formatted).unwrap(), formatted, "{source}");
+            assert_eq!(
+                crate::Program::compile(&formatted).unwrap(),
+                crate::Program::compile(source).unwrap()
+            );
+        }
+    }
     #[test]
-    fn loop_parsing() {
-        let program = "NN5[N10+]";
+    fn render_pixel_coordinates() {
+        use crate::render_pixel;
         assert_eq!(
-            (
-                9,
-                vec![
-                    Integer,
-                    Integer,
-                    Digit(5),
-                    Loop(vec![
-                        Integer,
-                        Digit(1),
-                        Digit(0),
-                        Arithmetic(crate::Arithmetic::Plus)
-                    ])
-                ]
-            ),
-            parse(program, 0, 0).unwrap()
+            render_pixel("XYT", 10, 20, 30).unwrap(),
+            RGB8::new(10, 20, 30)
+        );
+    }
+    #[test]
+    fn render_pixel_debug_halts() {
+        use crate::render_pixel;
+        assert!(matches!(
+            render_pixel("XYWT", 1, 2, 3),
+            Err(FxytError::Runtime {
+                error: RuntimeError::DebugHalt,
+                ..
+            })
+        ));
+    }
+    #[test]
+    #[cfg(feature = "std")]
+    fn debug_output() {
+        use crate::{render_with, RenderOptions};
+        use std::sync::{Arc, Mutex};
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let printed = || String::from_utf8(std::mem::take(&mut *output.lock().unwrap())).unwrap();
+        let mut options = RenderOptions {
+            debug_output: Some(output.clone()),
+            ..Default::default()
+        };
+        // only looks at the stack in the bottom left corner
+        let program = "XY XY+! ?[W] ^N0N0";
+
+        assert!(matches!(
+            render_with(program, &options),
+            Err(FxytError::Runtime {
+                error: RuntimeError::DebugHalt,
+                pos: Some(10),
+                ..
+            })
+        ));
+        assert_eq!(printed(), "(0, 0, 0) -> [0, 0]\n");
+
+        options.debug_halt = false;
+        let frame = render_with(program, &options).unwrap().remove(0);
+        assert_eq!(frame.image[255][5], RGB8::new(5, 0, 0));
+        assert_eq!(printed(), "(0, 0, 0) -> [0, 0]\n");
+    }
+    #[test]
+    fn render_pixel_divide_by_zero_modes() {
+        use crate::render_pixel;
+        // only divides by zero in the left hand column
+        assert!(matches!(
+            render_pixel("N100X/DD", 0, 0, 0),
+            Err(FxytError::Runtime {
+                error: RuntimeError::DivideByZero,
+                ..
+            })
+        ));
+        assert_eq!(
+            render_pixel("N100X/DD", 4, 0, 0).unwrap(),
+            RGB8::new(25, 25, 25)
+        );
+        assert_eq!(
+            render_pixel("MN100X/DD", 0, 0, 0).unwrap(),
+            RGB8::new(0, 0, 0)
+        );
+        assert_eq!(
+            render_pixel("MMN100X/DD", 0, 0, 0).unwrap(),
+            RGB8::new(255, 0, 0)
+        );
+    }
+    #[test]
+    fn render_frames_is_lazy() {
+        use crate::render_frames;
+        assert_eq!(
+            render_frames("XY]").err(),
+            Some(FxytError::Parse(ParseError::InvalidCharacter(2..3)))
+        );
+
+        // only the first couple of frames are ever rendered
+        let mut frames = render_frames("TDD").unwrap();
+        assert_eq!(
+            frames.next().unwrap().unwrap().image[0][0],
+            RGB8::new(0, 0, 0)
+        );
+        assert_eq!(
+            frames.next().unwrap().unwrap().image[0][0],
+            RGB8::new(1, 1, 1)
+        );
+
+        // runtime errors surface per frame, here once T gets past 0
+        let mut frames = render_frames("N1TN0=/DD").unwrap();
+        assert!(frames.next().unwrap().is_ok());
+        assert!(matches!(
+            frames.next().unwrap(),
+            Err(FxytError::Runtime {
+                error: RuntimeError::DivideByZero,
+                ..
+            })
+        ));
+    }
+    #[test]
+    fn compiled_program_renders_repeatedly() {
+        use crate::{render, render_frame, Program};
+        let program = Program::compile("XYT+^N256%DD").unwrap();
+        assert!(program.is_animated());
+        assert_eq!(
+            program.render_frame(3).unwrap(),
+            render_frame("XYT+^N256%DD", 3).unwrap()
+        );
+        assert_eq!(
+            program.render_frame(3).unwrap(),
+            program.render_frame(3).unwrap()
+        );
+        assert_eq!(program.render_pixel(1, 2, 3).unwrap(), RGB8::new(4, 4, 4));
+
+        let still = Program::compile("XY&N0N0").unwrap();
+        assert!(!still.is_animated());
+        assert_eq!(still.render().unwrap(), render("XY&N0N0").unwrap());
+        assert_eq!(still.render_sized(16, 16).unwrap()[0].height(), 16);
+
+        assert_eq!(
+            Program::compile("XYé").unwrap_err(),
+            ParseError::InvalidCharacter(2..4)
+        );
+    }
+    #[test]
+    fn animation_detection() {
+        use crate::{render_sized, Program};
+        let animated = |source| Program::compile(source).unwrap().is_animated();
+
+        // T reaches the colour
+        assert!(animated("TXY"));
+        assert!(animated("XYT^^"));
+        assert!(animated("TN1N1RS"));
+        assert!(animated("TN1N1'RN2"));
+        assert!(animated("N3[T+]N0N0"));
+        // T reaches something that might only fail on some frames
+        assert!(animated("TN2*PXY^"));
+        assert!(animated("N1TN0=/DD"));
+        assert!(animated("TFXY^"));
+        assert!(animated("T[N1]PXY^"));
+        assert!(animated("N1N2[T]PPXY^"));
+        assert!(animated("TN9>?[N1P]XY^"));
+        assert!(animated("N1L[TN1=]XY^"));
+
+        // T is used, but thrown away or buried before it can matter
+        assert!(!animated("TPXY^"));
+        assert!(!animated("TN5>PXY^"));
+        assert!(!animated("TD=PXY^"));
+        assert!(!animated("TXYN0N0"));
+        assert!(!animated("TN1N1RPXY^"));
+        assert!(!animated("TN1N1'RSPXY^"));
+        assert!(!animated("N3[TPXP]XY^"));
+        assert!(!animated("N3[TN1^P]N2[N1]XY^"));
+        assert!(!animated("N1?[TN1&P]XY^"));
+        assert!(!animated("N1L[TPN0]XY^"));
+
+        let frames = render_sized("TN5>PXY^", 4, 4).unwrap();
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames, render_sized("XY^", 4, 4).unwrap());
+    }
+    #[test]
+    #[cfg(feature = "png")]
+    fn png_round_trip() {
+        use crate::{render_sized, write_png};
+        let frame = render_sized("XY^XY&XY|", 256, 100).unwrap().remove(0);
+        let mut encoded = Vec::new();
+        write_png(&frame, &mut encoded).unwrap();
+
+        let mut reader = png::Decoder::new(encoded.as_slice()).read_info().unwrap();
+        let mut decoded = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut decoded).unwrap();
+        assert_eq!((info.width, info.height), (256, 100));
+        assert_eq!(info.color_type, png::ColorType::Rgb);
+
+        let pixels: Vec<u8> = frame
+            .image
+            .iter()
+            .flatten()
+            .flat_map(|pixel| [pixel.r, pixel.g, pixel.b])
+            .collect();
+        assert_eq!(decoded[..info.buffer_size()], pixels);
+    }
+    #[test]
+    #[cfg(feature = "apng")]
+    fn apng_frame_delays() {
+        use crate::{render_with, write_apng, RenderOptions};
+        let options = RenderOptions {
+            frames: Some(3),
+            ..Default::default()
+        };
+        let frames = render_with("TTTN25F", &options).unwrap();
+        let mut apng = Vec::new();
+        write_apng(&frames, &mut apng).unwrap();
+
+        let control = png_chunk(&apng, b"fcTL").unwrap();
+        assert_eq!(u16::from_be_bytes(control[20..22].try_into().unwrap()), 25);
+        assert_eq!(u16::from_be_bytes(control[22..24].try_into().unwrap()), 100);
+    }
+    #[test]
+    #[cfg(feature = "apng")]
+    fn apng_single_frame_is_still() {
+        use crate::{render, write_apng};
+        let mut apng = Vec::new();
+        write_apng(&render("XYX").unwrap(), &mut apng).unwrap();
+        assert!(png_chunk(&apng, b"IHDR").is_some());
+        assert!(png_chunk(&apng, b"acTL").is_none());
+    }
+    #[test]
+    #[cfg(all(feature = "gif", feature = "apng"))]
+    fn streaming_writers() {
+        use crate::{
+            render_with, write_apng, write_gif_with, ApngWriter, GifOptions, GifWriter, Program,
+            RenderOptions,
+        };
+        let options = RenderOptions::new().size(8, 8).frames(5);
+        let program = Program::compile("XYT^^ TN2=?[N7F]").unwrap();
+        let frames = program.render_frames_with(&options);
+        assert_eq!(frames.len(), 5);
+
+        // frame by frame comes out the same as all at once
+        let gif_options = GifOptions {
+            dither: true,
+            ..GifOptions::from_fps(50)
+        };
+        let mut gif = GifWriter::new(Vec::new(), 8, 8, &gif_options).unwrap();
+        let mut apng_out = Vec::new();
+        let mut apng = ApngWriter::new(&mut apng_out, 8, 8, 5).unwrap();
+        for frame in frames {
+            let frame = frame.unwrap();
+            gif.write_frame(&frame).unwrap();
+            apng.write_frame(&frame).unwrap();
+        }
+        apng.finish().unwrap();
+
+        let frames = render_with("XYT^^ TN2=?[N7F]", &options).unwrap();
+        let mut expected = Vec::new();
+        write_gif_with(&frames, &mut expected, &gif_options).unwrap();
+        assert_eq!(gif.finish().unwrap(), expected);
+        let mut expected = Vec::new();
+        write_apng(&frames, &mut expected).unwrap();
+        assert_eq!(apng_out, expected);
+
+        assert!(GifWriter::new(Vec::new(), 70_000, 8, &gif_options).is_err());
+    }
+    #[test]
+    fn packed_frame_bytes() {
+        use crate::{render_rgb, render_rgba};
+        let rgba = render_rgba("XYN0N50F").unwrap().remove(0);
+        assert_eq!((rgba.width, rgba.height, rgba.interval), (256, 256, 50));
+        assert_eq!(rgba.data.len(), 256 * 256 * 4);
+        // top left is (0, 255), bottom right is (255, 0)
+        assert_eq!(rgba.data[..4], [0, 255, 0, 255]);
+        assert_eq!(rgba.data[rgba.data.len() - 4..], [255, 0, 0, 255]);
+
+        let rgb = render_rgb("XYN0N50F").unwrap().remove(0);
+        assert_eq!(rgb.data.len(), 256 * 256 * 3);
+        assert_eq!(rgb.data[..3], [0, 255, 0]);
+        assert_eq!(rgb.data[rgb.data.len() - 3..], [255, 0, 0]);
+    }
+    #[test]
+    fn ansi_preview_averages_blocks() {
+        use crate::{preview_ansi, render_sized};
+        let frame = render_sized("XN64*YN64*N0", 4, 4).unwrap().remove(0);
+        assert_eq!(
+            preview_ansi(&frame, 2),
+            "\x1b[38;2;32;160;0m\x1b[48;2;32;32;0m▀\x1b[38;2;160;160;0m\x1b[48;2;160;32;0m▀\x1b[0m\n"
+        );
+
+        // an odd number of half rows leaves the bottom half of the last line empty
+        let frame = render_sized("XYN0", 4, 3).unwrap().remove(0);
+        let preview = preview_ansi(&frame, 4);
+        assert_eq!(preview.lines().count(), 2);
+        assert!(preview.lines().last().unwrap().contains("\x1b[49m▀"));
+
+        assert_eq!(preview_ansi(&frame, 0), "");
+    }
+    #[test]
+    fn whitespace_is_ignored() {
+        let dense = parse(b"N5[XN10+D]YY*", 0, 0, &mut Vec::new()).unwrap().1;
+        let spaced = parse(
+            b"  N5 [\n\tX N10 +\r\n\tD\n]\n Y Y * \n",
+            0,
+            0,
+            &mut Vec::new(),
         )
+        .unwrap()
+        .1;
+        assert_eq!(dense, spaced);
     }
     #[test]
-    fn loop_parsing_doubly_nested() {
-        let program = "NN5[N10[N4+]]";
+    fn whitespace_keeps_error_positions() {
+        assert_eq!(
+            parse(b"N5 [ X\n  \xFF ]", 0, 0, &mut Vec::new()).unwrap_err(),
+            ParseError::InvalidCharacter(9..10)
+        );
+        assert_eq!(
+            parse(b"X [ [ ] ] Y ]", 0, 0, &mut Vec::new()).unwrap_err(),
+            ParseError::InvalidCharacter(12..13)
+        );
+        assert!(matches!(
+            parse(b"X\n[ N5", 0, 0, &mut Vec::new()),
+            Err(ParseError::BracketMismatch(2))
+        ));
+        assert_eq!(parse(b"[ N1 ] ", 0, 0, &mut Vec::new()).unwrap().0, 7);
+    }
+    #[test]
+    fn comments_are_ignored() {
+        let bare = parse(b"N5[XN10+]YD", 0, 0, &mut Vec::new()).unwrap().1;
+        let commented = b"# before\nN5 # after N5\n[ # inside the loop [\nX N10 + # ]]]\n# right before the bracket\n]Y#\nD # at the very end";
+        assert_eq!(parse(commented, 0, 0, &mut Vec::new()).unwrap().1, bare);
+        assert_eq!(
+            parse("# ünïcødé is fine\nX".as_bytes(), 0, 0, &mut Vec::new())
+                .unwrap()
+                .1,
+            vec![Coordinates(crate::Coordinates::X)]
+        );
+    }
+    #[test]
+    fn comments_keep_error_positions() {
+        assert_eq!(
+            parse(b"X # comment\n\xFF", 0, 0, &mut Vec::new()).unwrap_err(),
+            ParseError::InvalidCharacter(12..13)
+        );
+        assert_eq!(
+            parse(b"[X # comment\n] ]", 0, 0, &mut Vec::new()).unwrap_err(),
+            ParseError::InvalidCharacter(15..16)
+        );
+        assert!(matches!(
+            parse(b"N1[X # ]", 0, 0, &mut Vec::new()),
+            Err(ParseError::BracketMismatch(2))
+        ));
+    }
+    #[test]
+    fn program_from_str() {
+        use crate::Program;
+        let program: Program = "XY^".parse().unwrap();
+        assert_eq!(program, Program::compile("XY^").unwrap());
         assert_eq!(
+            "XY^?".parse::<Program>().unwrap_err(),
+            ParseError::InvalidCharacter(3..4)
+        );
+        assert!(matches!(
+            "X[Y".parse::<Program>(),
+            Err(ParseError::BracketMismatch(1))
+        ));
+    }
+    #[test]
+    fn command_display() {
+        use crate::{Arithmetic, Bitwise, Comparison, Coordinates, StackOperation};
+        let commands = [
+            (Command::Coordinates(Coordinates::X), "X"),
+            (Command::Coordinates(Coordinates::Y), "Y"),
+            (Command::Coordinates(Coordinates::T), "T"),
+            (Command::Integer, "N"),
+            (Command::Literal(42), "N42"),
+            (Command::Literal(-3), "N3~"),
+            (Command::Literal(isize::MIN), "N9223372036854775807~N1-"),
+            (Command::Digit(7), "7"),
+            (Command::Arithmetic(Arithmetic::Plus), "+"),
+            (Command::Arithmetic(Arithmetic::Minus), "-"),
+            (Command::Arithmetic(Arithmetic::Times), "*"),
+            (Command::Arithmetic(Arithmetic::Divide), "/"),
+            (Command::Arithmetic(Arithmetic::Modulus), "%"),
+            (Command::Arithmetic(Arithmetic::Power), "E"),
+            (Command::Mode, "M"),
+            (Command::Comparison(Comparison::Equals), "="),
+            (Command::Comparison(Comparison::LessThan), "<"),
+            (Command::Comparison(Comparison::GreaterThan), ">"),
+            (Command::Comparison(Comparison::AtMost), "<="),
+            (Command::Comparison(Comparison::AtLeast), ">="),
+            (Command::Invert, "!"),
+            (Command::Bitwise(Bitwise::Xor), "^"),
+            (Command::Bitwise(Bitwise::And), "&"),
+            (Command::Bitwise(Bitwise::Or), "|"),
+            (Command::Bitwise(Bitwise::ShiftLeft), "("),
+            (Command::Bitwise(Bitwise::ShiftRight), ")"),
+            (Command::Bitwise(Bitwise::ShiftRightLogical), "')"),
+            (Command::Clip, "C"),
+            (Command::SmoothStep, "\""),
+            (Command::EaseInOut, "\\"),
+            (Command::StackOperation(StackOperation::Duplicate), "D"),
+            (Command::StackOperation(StackOperation::DuplicatePair), "'D"),
+            (Command::StackOperation(StackOperation::Pop), "P"),
+            (Command::StackOperation(StackOperation::Swap), "S"),
+            (Command::StackOperation(StackOperation::Rotate), "R"),
+            (Command::StackOperation(StackOperation::RotateBack), "'R"),
+            (Command::StackOperation(StackOperation::Over), "O"),
+            (Command::StackOperation(StackOperation::SwapDeep), "J"),
+            (Command::StackOperation(StackOperation::Roll), "`"),
             (
-                13,
-                vec![
-                    Integer,
-                    Integer,
-                    Digit(5),
-                    Loop(vec![
-                        Integer,
-                        Digit(1),
-                        Digit(0),
-                        Loop(vec![Integer, Digit(4), Arithmetic(crate::Arithmetic::Plus)])
-                    ])
-                ]
+                Command::Loop(vec![Command::Integer, Command::Digit(1)]),
+                "[N1]",
             ),
-            parse(program, 0, 0).unwrap()
-        )
+            (Command::Conditional(vec![Command::Clear]), "?[Z]"),
+            (Command::While(vec![]), "L[]"),
+            (Command::BitReverse, "B"),
+            (Command::DigitalRoot, "U"),
+            (Command::Spiral, "V"),
+            (Command::Accumulate, "_"),
+            (Command::FrameInterval, "F"),
+            (Command::Debug, "W"),
+            (Command::Absolute, "A"),
+            (Command::Negate, "~"),
+            (Command::SquareRoot, "Q"),
+            (Command::Clear, "Z"),
+            (Command::Depth, "@"),
+            (Command::Min, "{"),
+            (Command::Max, "}"),
+            (Command::Hash, "H"),
+            (Command::Store(2), ":2"),
+            (Command::Load(3), ";3"),
+            (Command::Gcd, "G"),
+            (Command::Clamp, "K"),
+            (Command::ModPow, "I"),
+            (Command::ScaledTime, "'T"),
+            (Command::Width, ","),
+            (Command::Height, "."),
+        ];
+        for (command, expected) in commands {
+            assert_eq!(command.to_string(), expected);
+        }
     }
     #[test]
-    fn loop_parsing_many_empty_nested() {
-        let program = "[[[[]]]]";
+    fn source_round_trip() {
+        use crate::Program;
+        let corpus = [
+            "XY^",
+            "x y ^ n128 %",
+            "N0N1N5[D_N1+]PN0N0",
+            "NN5[N10[N4+]]",
+            "[[[[]]]]",
+            "TN2/D*N256%XY&MN1N0/!<>=|CDPSRFW",
+            "N16VBU{}()AQE~@ZO?[N1?[]]L[N0]<=>= # all the newer commands\n",
+            "XY< =N0[> =]> <=",
+            "$1F$0 $AB",
+            "N3HXYH^",
+            "X:0 ;0 ;3:3",
+            "XYGN4*D",
+            "XN64N192K YN0N100K T",
+            "XYT N2J N1J",
+            "X,N2/- Y.N2/- ,.*",
+            "XYN256I N3 N100 N7I",
+        ];
+        for source in corpus {
+            let program = Program::compile(source).unwrap();
+            let printed = program.to_string();
+            assert_eq!(Program::compile(&printed).unwrap(), program, "{source}");
+        }
         assert_eq!(
-            (8, vec![Loop(vec![Loop(vec![Loop(vec![Loop(vec![])])])])]),
-            parse(program, 0, 0).unwrap()
-        )
+            Program::compile("x y ^ n128 % # comment")
+                .unwrap()
+                .to_string(),
+            "XY^N128%"
+        );
     }
     #[test]
-    fn loop_parsing_twice_empty_nested() {
-        let program = "[[]]";
+    #[cfg(feature = "serde")]
+    fn serde_round_trip() {
+        use crate::Program;
+        let program = Program::compile("N0N1N5[D_N1+[XY^]]PN0N0").unwrap();
+        let json = serde_json::to_string(&program).unwrap();
+        assert_eq!(serde_json::from_str::<Program>(&json).unwrap(), program);
+
+        let json = serde_json::to_string(&Program::compile("XN12+[D]").unwrap()).unwrap();
         assert_eq!(
-            (4, vec![Loop(vec![Loop(vec![])])]),
-            parse(program, 0, 0).unwrap()
-        )
+            json,
+            r#"[{"Coordinates":"X"},"Integer",{"Digit":1},{"Digit":2},{"Arithmetic":"Plus"},{"Loop":[{"StackOperation":"Duplicate"}]}]"#
+        );
+    }
+    #[test]
+    fn optimize_folds_constants() {
+        use crate::{Arithmetic, Program, RenderOptions};
+        let mut program = Program::compile("N2N3*N4+ XN10% N3[N1N2+P]").unwrap();
+        program.optimize(&RenderOptions::default());
+        assert_eq!(
+            program.commands,
+            vec![
+                Literal(10),
+                Coordinates(crate::Coordinates::X),
+                Literal(10),
+                Arithmetic(Arithmetic::Modulus),
+                Literal(3),
+                Loop(vec![Literal(3), StackOperation(crate::StackOperation::Pop)]),
+            ]
+        );
+
+        // these would change what happens, so they have to stay as they are
+        for source in [
+            "N1N0/",
+            "N9223372036854775807N1+",
+            "N1N2N3N4N5N6N7N8N9+",
+            "XN2[D]N1N2+",
+        ] {
+            let mut program = Program::compile(source).unwrap();
+            program.optimize(&RenderOptions::default());
+            assert!(
+                program
+                    .commands
+                    .contains(&Command::Arithmetic(Arithmetic::Plus))
+                    || program
+                        .commands
+                        .contains(&Command::Arithmetic(Arithmetic::Divide)),
+                "{source}"
+            );
+        }
+
+        // the stack limit it's folded for is the one it has to keep to
+        let options = RenderOptions::new().max_stack(3);
+        let mut program = Program::compile("N1N2N3N4+PP").unwrap();
+        let unoptimized = program.render_with(&options).unwrap_err();
+        program.optimize(&options);
+        assert_eq!(program.render_with(&options).unwrap_err(), unoptimized);
+        assert!(matches!(
+            unoptimized,
+            FxytError::Runtime {
+                error: RuntimeError::StackOverflow,
+                ..
+            }
+        ));
+        program.optimize(&RenderOptions::default());
+        let pop = StackOperation(crate::StackOperation::Pop);
+        assert_eq!(
+            program.commands,
+            [Literal(1), Literal(2), Literal(7), pop.clone(), pop]
+        );
+    }
+    #[test]
+    fn optimize_preserves_output() {
+        use crate::{Program, RenderOptions};
+        let corpus = [
+            "XY^",
+            "N2N3*X+N7%N1N0+Y*N255%",
+            "N1N0/X+",
+            "MN1N0/X+",
+            "MMN5N0%",
+            "N9223372036854775807N1+",
+            "N1N2N3N4N5N6N7N8N9+",
+            "XN1N2N3N4N5N6N7+PPPPPP",
+            "XYN1N2N3N4N5N6N7+PPPPPP",
+            "N3[N1N2+P]N5N5*",
+            "XN4%[N1N1+]N1N1N1N1N1N1+",
+            "N0N5-N7*X+C",
+            "N12N34=N5N5=|X&N1N2<+N2N1>+",
+            "N16N9BN1_",
+            "XN2/N1N2+F",
+            "N3N9{N1N2}X}",
+            "N1N3(X(N256N2)Y)",
+            "XN3') N0N1~N60')",
+            "XYT'R N1'RR",
+            "XY'D^ T'DPP",
+            "XYT N2` N1`",
+            "X'T^ Y'T& 'T",
+            r#"X" Y\ T"\"#,
+            "N0N5-AXN3-A+N0N9223372036854775807-N1-AP",
+            "N50QXQ+N0N4-Q",
+            "N2N5EXN2E+N2N99E",
+            "N2N0N1-E",
+            "N5~X~+~N0~N9223372036854775807~N1-~",
+            "@@N2*X@+",
+            "XN3[D]@N0",
+            "N1N1N1N1N1N1N1N1@",
+            "XN3[D]ZN1N2+@Y",
+            "XN2%?[N1N2+]N3N4*?[N5N6+D]XN100<?[MN1N0/]",
+            "XN1L[N1-DN0>]N2N3+D",
+            "XN4%L[N1N1+P]N0N0",
+        ];
+        for source in corpus {
+            let program = Program::compile(source).unwrap();
+            let mut optimized = program.clone();
+            optimized.optimize(&RenderOptions::default());
+            assert_eq!(
+                format!("{:?}", optimized.render_sized(16, 16)),
+                format!("{:?}", program.render_sized(16, 16)),
+                "{source}"
+            );
+
+            // printing an optimized program keeps its behaviour, if not its exact commands (or
+            // where they are)
+            let without_pos = |program: &Program| match program.render_sized(16, 16) {
+                Err(FxytError::Runtime { error, coords, .. }) => format!("{error:?} at {coords}"),
+                result => format!("{result:?}"),
+            };
+            let reprinted = Program::compile(&optimized.to_string()).unwrap();
+            assert_eq!(without_pos(&reprinted), without_pos(&program), "{source}");
+        }
+
+        // including when the stack is as full as it's allowed to get
+        let options = RenderOptions::new().size(1, 1).max_stack(3);
+        for source in ["N1YN5~<N0", "N1N9223372036854775807~N1-Y<N0"] {
+            let program = Program::compile(source).unwrap();
+            let mut optimized = program.clone();
+            optimized.optimize(&options);
+            let reprinted = Program::compile(&optimized.to_string()).unwrap();
+            assert_eq!(
+                reprinted.render_with(&options).unwrap(),
+                program.render_with(&options).unwrap(),
+                "{source}"
+            );
+        }
+    }
+    #[test]
+    fn random_programs_never_panic() {
+        use crate::{parse_all, render_sampled, ColourMode, Program, RenderOptions};
+        // mostly commands, with the odd thing that isn't one
+        const BYTES: &[u8] = b"XYTN0123456789$ABCDEFabcdef+-*/%E<>=!^&|()CDPSROJ`'\"[]?L[]\
+            BUVHGKI_FWAQ~Z@{}:;,.M \n#\xC3\xA9\xFF\x00";
+        let mut seed = 0x2545_F491_4F6C_DD1Du64;
+        let mut random = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+
+        let options = RenderOptions::new()
+            .size(3, 3)
+            .frames(2)
+            .max_ops(2_000)
+            .debug_halt(false);
+        #[cfg(feature = "std")]
+        let options =
+            options.debug_output(std::sync::Arc::new(std::sync::Mutex::new(std::io::sink())));
+        let extremes = [0, 1, 255, isize::MAX, isize::MIN, -1];
+        for _ in 0..3_000 {
+            let len = random() % 24;
+            let source: Vec<u8> = (0..len)
+                .map(|_| BYTES[random() as usize % BYTES.len()])
+                .collect();
+            let (_, errors) = parse_all(&String::from_utf8_lossy(&source));
+            let Ok(program) = Program::compile_bytes(&source) else {
+                assert!(!errors.is_empty());
+                continue;
+            };
+
+            let _ = program.info();
+            let _ = Program::compile(&program.format()).unwrap();
+            let mut optimized = program.clone();
+            optimized.optimize(&options);
+            for options in [
+                options.clone(),
+                options.clone().samples(2).colours(ColourMode::Hsv),
+                options.clone().t_start(usize::MAX).seamless(true),
+            ] {
+                let _ = program.render_with(&options);
+                let _ = optimized.render_with(&options);
+                let coords = Coords {
+                    x: extremes[random() as usize % extremes.len()],
+                    y: extremes[random() as usize % extremes.len()],
+                    t: extremes[random() as usize % extremes.len()],
+                };
+                let _ = render_sampled::<RGB8>(
+                    &program,
+                    &mut Vec::new(),
+                    &mut None,
+                    coords,
+                    &options,
+                    None,
+                );
+            }
+        }
+
+        // too long a count to turn up by chance, and nothing in the body for the budget to see
+        let program = Program::compile("N99999999999999[]").unwrap();
+        assert!(program.render_with(&options).is_err());
     }
 }
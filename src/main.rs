@@ -0,0 +1,283 @@
+//! Renders a FXYT program to an image file, `output.gif` unless told otherwise.
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::Path;
+use std::process::ExitCode;
+
+use fxyt::{
+    describe_error, ApngWriter, Frame, FxytError, GifOptions, GifWriter, Program, RenderOptions,
+};
+
+const USAGE: &str = "\
+usage: fxyt [options] <program>
+       fxyt [options] -f <file>
+       fxyt [options] -
+
+Renders a FXYT program to output.gif. Short programs can go straight on the command line;
+longer ones can be read from a file with -f, or from standard input with -.
+
+options:
+    -o <output>  Where to write the image, output.gif by default. The format comes from the
+                 extension: .gif or .apng for the whole animation, or .png or .ppm for just
+                 the first frame.
+    --frames <n> Render only the first n frames of an animation, instead of all 256.
+    --fps <n>    Play a GIF at n frames per second, rounded to the nearest centisecond,
+                 apart from frames whose interval is set with F.
+    --preview    Draw the first frame in the terminal, which needs truecolour support,
+                 instead of writing it anywhere.
+    -h, --help   Show this message.";
+
+/// How many characters wide `--preview` draws the frame.
+const PREVIEW_COLUMNS: usize = 64;
+
+struct Args {
+    source: Source,
+    output: String,
+    frames: Option<usize>,
+    fps: Option<u32>,
+    preview: bool,
+}
+
+/// Where to read the program from.
+enum Source {
+    Argument(String),
+    File(String),
+    Stdin,
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => {
+            eprintln!("{message}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// The image formats that can be written, picked by the output's extension.
+#[derive(Clone, Copy)]
+enum Format {
+    Gif,
+    Apng,
+    Png,
+    Ppm,
+}
+
+impl Format {
+    fn from_path(path: &str) -> Result<Self, String> {
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_ascii_lowercase);
+        match extension.as_deref() {
+            Some("gif") => Ok(Format::Gif),
+            Some("apng") => Ok(Format::Apng),
+            Some("png") => Ok(Format::Png),
+            Some("ppm") => Ok(Format::Ppm),
+            _ => Err(format!(
+                "Can't tell what format to write {path} in, it should end in .gif, .apng, .png \
+                 or .ppm"
+            )),
+        }
+    }
+}
+
+fn run() -> Result<(), String> {
+    let Some(args) = parse_args(std::env::args().skip(1))? else {
+        println!("{USAGE}");
+        return Ok(());
+    };
+    if args.preview {
+        let program = Program::compile_bytes(&read_program(&args.source)?)
+            .map_err(|error| describe_error(&error))?;
+        let frame = program
+            .render_frame(0)
+            .map_err(|error| describe_error(&error))?;
+        print!("{}", fxyt::preview_ansi(&frame, PREVIEW_COLUMNS));
+        return Ok(());
+    }
+    // before rendering, so a typo doesn't cost a whole render
+    let format = Format::from_path(&args.output)?;
+    let program = read_program(&args.source)?;
+
+    let program = Program::compile_bytes(&program).map_err(|error| describe_error(&error))?;
+    if args.frames.is_some() && !program.is_animated() {
+        eprintln!("warning: ignoring --frames, the program doesn't change over time");
+    }
+    if args.fps.is_some() && !matches!(format, Format::Gif) {
+        eprintln!("warning: ignoring --fps, it only applies to GIFs");
+    }
+    let options = RenderOptions {
+        frames: args.frames,
+        ..Default::default()
+    };
+
+    let output = &args.output;
+    let create = || {
+        File::create(output)
+            .map(BufWriter::new)
+            .map_err(|error| format!("Couldn't create {output}: {error}"))
+    };
+    let write_error = |error: io::Error| format!("Couldn't write {output}: {error}");
+    match format {
+        // no point rendering frames that won't be written
+        Format::Png => {
+            let frame = program
+                .render_frame(0)
+                .map_err(|error| describe_error(&error))?;
+            fxyt::write_png(&frame, create()?).map_err(write_error)
+        }
+        Format::Ppm => {
+            let frame = program
+                .render_frame(0)
+                .map_err(|error| describe_error(&error))?;
+            fxyt::write_ppm(&frame, create()?).map_err(write_error)
+        }
+        Format::Gif | Format::Apng => {
+            let mut frames = program.render_frames_with(&options);
+            let count = frames.len();
+            // rendered before the file is created, so a program that fails everywhere doesn't
+            // leave one behind
+            let Some(first) = frames.next() else {
+                return Ok(());
+            };
+            let first = first.map_err(|error| describe_error(&error))?;
+            let result = write_animation(format, first, frames, count, create()?, args.fps);
+            if result.is_err() {
+                // a partial animation is no use to anyone
+                let _ = fs::remove_file(output);
+            }
+            result.map_err(|error| match error {
+                AnimationError::Render(error) => describe_error(&error),
+                AnimationError::Write(error) => write_error(error),
+            })
+        }
+    }
+}
+
+/// Why an animation couldn't be written.
+enum AnimationError {
+    Render(FxytError),
+    Write(io::Error),
+}
+
+impl From<FxytError> for AnimationError {
+    fn from(error: FxytError) -> Self {
+        AnimationError::Render(error)
+    }
+}
+
+impl From<io::Error> for AnimationError {
+    fn from(error: io::Error) -> Self {
+        AnimationError::Write(error)
+    }
+}
+
+/// Writes each frame as soon as it's rendered, so a long animation is never all in memory at
+/// once. `count` includes the `first` frame.
+fn write_animation(
+    format: Format,
+    first: Frame,
+    rest: impl Iterator<Item = Result<Frame, FxytError>>,
+    count: usize,
+    out: impl Write,
+    fps: Option<u32>,
+) -> Result<(), AnimationError> {
+    let (width, height) = (first.width(), first.height());
+    match format {
+        Format::Gif => {
+            let options = fps.map_or_else(GifOptions::default, GifOptions::from_fps);
+            let mut writer = GifWriter::new(out, width, height, &options)?;
+            writer.write_frame(&first)?;
+            for frame in rest {
+                writer.write_frame(&frame?)?;
+            }
+            writer.finish()?.flush()?;
+        }
+        // like write_apng, a still program is better off as a plain PNG
+        Format::Apng if count == 1 => fxyt::write_png(&first, out)?,
+        Format::Apng => {
+            let mut writer = ApngWriter::new(out, width, height, count)?;
+            writer.write_frame(&first)?;
+            for frame in rest {
+                writer.write_frame(&frame?)?;
+            }
+            writer.finish()?;
+        }
+        Format::Png | Format::Ppm => unreachable!("only animations are written frame by frame"),
+    }
+    Ok(())
+}
+
+/// The arguments, or `None` if only the usage was asked for.
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Option<Args>, String> {
+    let mut source = None;
+    let mut output = None;
+    let mut frames = None;
+    let mut fps = None;
+    let mut preview = false;
+    while let Some(arg) = args.next() {
+        let next = match arg.as_str() {
+            "-f" => Source::File(args.next().ok_or("-f needs a file to read")?),
+            "-o" => {
+                output = Some(args.next().ok_or("-o needs a file to write")?);
+                continue;
+            }
+            "--frames" => {
+                let count = args.next().ok_or("--frames needs a number of frames")?;
+                match count.parse() {
+                    Ok(count) if count > 0 => frames = Some(count),
+                    _ => return Err(format!("--frames needs a positive number, not {count}")),
+                }
+                continue;
+            }
+            "--fps" => {
+                let rate = args
+                    .next()
+                    .ok_or("--fps needs a number of frames per second")?;
+                match rate.parse() {
+                    Ok(rate) if rate > 0 => fps = Some(rate),
+                    _ => return Err(format!("--fps needs a positive number, not {rate}")),
+                }
+                continue;
+            }
+            "--preview" => {
+                preview = true;
+                continue;
+            }
+            "-" => Source::Stdin,
+            "-h" | "--help" => return Ok(None),
+            _ => Source::Argument(arg),
+        };
+        if source.replace(next).is_some() {
+            return Err(format!(
+                "Only one program can be rendered at a time\n\n{USAGE}"
+            ));
+        }
+    }
+    Ok(Some(Args {
+        source: source.ok_or(USAGE)?,
+        output: output.unwrap_or_else(|| "output.gif".into()),
+        frames,
+        fps,
+        preview,
+    }))
+}
+
+fn read_program(source: &Source) -> Result<Vec<u8>, String> {
+    match source {
+        Source::Argument(program) => Ok(program.clone().into_bytes()),
+        Source::File(path) => {
+            fs::read(path).map_err(|error| format!("Couldn't read {path}: {error}"))
+        }
+        Source::Stdin => {
+            let mut program = Vec::new();
+            io::stdin()
+                .read_to_end(&mut program)
+                .map_err(|error| format!("Couldn't read standard input: {error}"))?;
+            Ok(program)
+        }
+    }
+}
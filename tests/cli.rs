@@ -0,0 +1,179 @@
+#![cfg(all(feature = "gif", feature = "apng"))]
+
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+/// A fresh directory for the binary to write its output into.
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("fxyt-cli-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn fxyt(dir: &PathBuf, args: &[&str], stdin: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_fxyt"))
+        .args(args)
+        .current_dir(dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(stdin).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+/// The size and frame count of a GIF.
+fn gif_shape(path: PathBuf) -> (u16, u16, usize) {
+    let file = std::fs::File::open(path).unwrap();
+    let mut decoder = gif::DecodeOptions::new().read_info(file).unwrap();
+    let mut frames = 0;
+    while decoder.read_next_frame().unwrap().is_some() {
+        frames += 1;
+    }
+    (decoder.width(), decoder.height(), frames)
+}
+
+#[test]
+fn program_from_stdin() {
+    let dir = scratch_dir("stdin");
+    let output = fxyt(&dir, &["-"], b"# a comment\nXY^\nD D\n");
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(gif_shape(dir.join("output.gif")), (256, 256, 1));
+}
+
+#[test]
+fn program_from_file_and_argument() {
+    let dir = scratch_dir("file");
+    std::fs::write(dir.join("program.fxyt"), "XY|\nN0N0 # red only\n").unwrap();
+    let output = fxyt(&dir, &["-f", "program.fxyt"], b"");
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(gif_shape(dir.join("output.gif")), (256, 256, 1));
+
+    std::fs::remove_file(dir.join("output.gif")).unwrap();
+    let output = fxyt(&dir, &["XY&DD"], b"");
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(gif_shape(dir.join("output.gif")), (256, 256, 1));
+}
+
+#[test]
+fn usage_and_errors() {
+    let dir = scratch_dir("errors");
+    let output = fxyt(&dir, &[], b"");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("usage: fxyt"));
+
+    // asking for it isn't a failure
+    for help in ["-h", "--help"] {
+        let output = fxyt(&dir, &[help], b"");
+        assert!(output.status.success(), "{output:?}");
+        assert!(String::from_utf8_lossy(&output.stdout).starts_with("usage: fxyt"));
+    }
+
+    let output = fxyt(&dir, &["-f", "missing.fxyt"], b"");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("missing.fxyt"));
+
+    // parse errors keep their position
+    let output = fxyt(&dir, &["-"], b"XY]");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("position `2`"));
+    assert!(!dir.join("output.gif").exists());
+
+    // nor does failing partway through an animation, after some frames were written
+    for output_file in ["output.gif", "output.apng"] {
+        let output = fxyt(&dir, &["-o", output_file, "XYT^^ TN5=?[N1N0/]"], b"");
+        assert!(!output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr).contains("divide by zero"));
+        assert!(!dir.join(output_file).exists());
+    }
+}
+
+#[test]
+fn output_format_from_extension() {
+    let dir = scratch_dir("formats");
+    let animated = "XYT^^N5F";
+
+    let output = fxyt(&dir, &["-o", "still.gif", "XY^DD"], b"");
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(gif_shape(dir.join("still.gif")), (256, 256, 1));
+
+    let output = fxyt(&dir, &["-o", "first.png", animated], b"");
+    assert!(output.status.success(), "{output:?}");
+    let decoder = png::Decoder::new(std::fs::File::open(dir.join("first.png")).unwrap());
+    let reader = decoder.read_info().unwrap();
+    assert!(reader.info().animation_control().is_none());
+
+    let output = fxyt(&dir, &["-o", "whole.apng", animated], b"");
+    assert!(output.status.success(), "{output:?}");
+    let decoder = png::Decoder::new(std::fs::File::open(dir.join("whole.apng")).unwrap());
+    let reader = decoder.read_info().unwrap();
+    assert_eq!(reader.info().animation_control().unwrap().num_frames, 256);
+
+    let output = fxyt(&dir, &["-o", "first.PPM", animated], b"");
+    assert!(output.status.success(), "{output:?}");
+    let ppm = std::fs::read(dir.join("first.PPM")).unwrap();
+    assert!(ppm.starts_with(b"P6\n256 256\n255\n"));
+    assert_eq!(ppm.len(), 15 + 256 * 256 * 3);
+
+    let output = fxyt(&dir, &["-o", "picture.jpg", animated], b"");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("picture.jpg"));
+    assert!(!dir.join("picture.jpg").exists());
+}
+
+#[test]
+fn frame_count() {
+    let dir = scratch_dir("frames");
+    let output = fxyt(&dir, &["--frames", "30", "-o", "short.gif", "XYT^^"], b"");
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(gif_shape(dir.join("short.gif")), (256, 256, 30));
+
+    // still programs only ever have the one frame
+    let output = fxyt(&dir, &["--frames", "30", "-o", "still.gif", "XY^DD"], b"");
+    assert!(output.status.success(), "{output:?}");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("warning"));
+    assert_eq!(gif_shape(dir.join("still.gif")), (256, 256, 1));
+
+    for bad in ["0", "many"] {
+        let output = fxyt(&dir, &["--frames", bad, "XYT^^"], b"");
+        assert!(!output.status.success());
+    }
+}
+
+#[test]
+fn frames_per_second() {
+    let dir = scratch_dir("fps");
+    let output = fxyt(&dir, &["--fps", "25", "--frames", "2", "XYT^^"], b"");
+    assert!(output.status.success(), "{output:?}");
+    let file = std::fs::File::open(dir.join("output.gif")).unwrap();
+    let mut decoder = gif::DecodeOptions::new().read_info(file).unwrap();
+    let mut delays = Vec::new();
+    while let Some(frame) = decoder.read_next_frame().unwrap() {
+        delays.push(frame.delay);
+    }
+    assert_eq!(delays, [4, 4]);
+
+    let output = fxyt(&dir, &["--fps", "25", "-o", "still.png", "XY^DD"], b"");
+    assert!(output.status.success(), "{output:?}");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("warning"));
+
+    for bad in ["0", "fast"] {
+        let output = fxyt(&dir, &["--fps", bad, "XYT^^"], b"");
+        assert!(!output.status.success());
+    }
+}
+
+#[test]
+fn terminal_preview() {
+    let dir = scratch_dir("preview");
+    let output = fxyt(&dir, &["--preview", "N255N0N0"], b"");
+    assert!(output.status.success(), "{output:?}");
+    let preview = String::from_utf8(output.stdout).unwrap();
+    // 64 columns of 256 pixels square is 64 half rows, drawn two to a line
+    assert_eq!(preview.lines().count(), 32);
+    assert!(preview.starts_with("\x1b[38;2;255;0;0m\x1b[48;2;255;0;0m▀"));
+    assert!(!dir.join("output.gif").exists());
+}